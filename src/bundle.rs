@@ -0,0 +1,38 @@
+use crate::{expression::Expression, program::Program};
+use anyhow::{ensure, Context, Result};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+const MAGIC: &[u8; 4] = b"olc\0";
+
+/// Bundles are only guaranteed to load in the same version of `ol` that
+/// created them, since the layout of the resolved program is not stable.
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub fn write(path: &Path, program: &Program<Expression>) -> Result<()> {
+    let mut writer =
+        BufWriter::new(File::create(path).context("failed to create bundle")?);
+    writer.write_all(MAGIC)?;
+    bincode::serialize_into(&mut writer, &(VERSION, program))?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn read(path: &Path) -> Result<Program<Expression>> {
+    let mut reader =
+        BufReader::new(File::open(path).context("failed to open bundle")?);
+    let mut magic = [0; MAGIC.len()];
+    reader
+        .read_exact(&mut magic)
+        .context("file is not a bundle")?;
+    ensure!(magic == *MAGIC, "file is not a bundle");
+    let version: String = bincode::deserialize_from(&mut reader)?;
+    ensure!(
+        version == VERSION,
+        "bundle was created by version {version} of ol, not {VERSION}"
+    );
+    Ok(bincode::deserialize_from(&mut reader)?)
+}
@@ -0,0 +1,19 @@
+use crate::{expression::Expression, value::Value};
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+
+/// An anonymous function along with the local variables that were in scope
+/// where it was created.
+///
+/// Those are captured by value, so assigning to one of them inside the closure
+/// only lasts until that call returns.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Closure {
+    pub parameter_count: usize,
+    pub captured: Vec<Value>,
+    /// For a closure bound by `let rec`, where in `captured` the closure
+    /// itself goes. It is filled in on each call rather than stored, since the
+    /// closure would otherwise keep itself alive.
+    pub recursive_slot: Option<usize>,
+    pub body: Rc<Expression>,
+}
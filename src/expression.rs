@@ -1,27 +1,75 @@
 use crate::value::Value;
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
 
 pub type Expression = Of<(), usize>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Of<NewVar, GetVar> {
     Literal(Value),
     MethodCall {
         name: String,
         this: Box<Self>,
         arguments: Vec<Self>,
+        /// Where the call is in the source code, for error messages.
+        line: usize,
     },
     LocalVariable {
         name_or_de_bruijn_index: GetVar,
     },
     LetIn {
         name: NewVar,
+        is_mutable: bool,
+        /// `let rec`, where the name is also in scope in `bound`, which is
+        /// always a closure.
+        is_recursive: bool,
         bound: Box<Self>,
         body: Box<Self>,
     },
+    Assign {
+        name_or_de_bruijn_index: GetVar,
+        value: Box<Self>,
+    },
     IfThenElse {
         condition: Box<Self>,
         if_true: Box<Self>,
         if_false: Box<Self>,
     },
+    While {
+        condition: Box<Self>,
+        body: Box<Self>,
+    },
+    Return(Box<Self>),
+    Break,
+    Continue,
     Do(Vec<Self>),
+    /// `match subject { pattern => body; _ => default }`, where each pattern
+    /// is a literal that the subject is compared to.
+    Match {
+        subject: Box<Self>,
+        arms: Vec<(Value, Self)>,
+        default: Option<Box<Self>>,
+    },
+    /// `new ClassName`
+    New(String),
+    /// `fn parameters -> body`. The body is reference counted since every
+    /// closure created from this expression shares it.
+    Closure {
+        parameters: Vec<NewVar>,
+        body: Rc<Self>,
+    },
+    /// `[element for name in iterable if condition]`
+    ListComprehension {
+        element: Box<Self>,
+        name: NewVar,
+        iterable: Box<Self>,
+        condition: Option<Box<Self>>,
+    },
+    /// `object?.field`, which is `()` rather than an error when `object` is
+    /// `()` or has no such field. `()` stands in for absence since there is no
+    /// option type, so a chain like `a?.b?.c` stops at the first gap.
+    OptionalFieldGet {
+        object: Box<Self>,
+        field: String,
+    },
 }
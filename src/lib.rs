@@ -0,0 +1,65 @@
+//! An interpreter for ol, usable both through the `ol` binary and embedded in
+//! other programs.
+
+#![forbid(unsafe_code, clippy::unwrap_used)]
+#![warn(clippy::nursery, clippy::pedantic)]
+// Nearly everything can fail because of the program being run, which says
+// more about the program than about the function.
+#![allow(clippy::missing_errors_doc)]
+#![allow(clippy::must_use_candidate, clippy::return_self_not_must_use)]
+
+pub mod bundle;
+pub mod closure;
+pub mod expression;
+pub mod load;
+pub mod method;
+pub mod object;
+pub mod parse;
+pub mod program;
+pub mod repl;
+pub mod resolve;
+pub mod typ;
+pub mod value;
+pub mod vm;
+
+use anyhow::{ensure, Context, Result};
+use std::{collections::HashMap, hash::BuildHasher};
+use value::Value;
+use vm::{ClassID, VM};
+
+/// Runs a program made up of a single source file, returning what `main`
+/// returned.
+pub fn run_source(source: &str) -> Result<Value> {
+    let program = parse::program(source).context("syntax error")?;
+    ensure!(
+        program.imports.is_empty(),
+        "imports are only supported in programs loaded from a file"
+    );
+    let program = resolve::program(program, &mut resolve::Warnings::default())?;
+    let mut vm = VM::new();
+    let class_ids = vm.load_program(program)?;
+    vm.run(main_class(&class_ids)?)
+}
+
+/// Evaluates a single expression in `vm`, like a line of REPL input.
+pub fn eval_expression(vm: &mut VM, source: &str) -> Result<Value> {
+    let expression =
+        parse::single_expression(source).context("syntax error")?;
+    let expression =
+        resolve::Resolver::default().resolve_expression(expression)?;
+    vm.evaluate(&expression)
+}
+
+/// Finds the class whose `main` method starts the program.
+pub fn main_class<S: BuildHasher>(
+    class_ids: &HashMap<String, ClassID, S>,
+) -> Result<ClassID> {
+    ensure!(
+        !class_ids.is_empty(),
+        "program is empty; it needs a `Main` class with a `main` method"
+    );
+    class_ids
+        .get("Main")
+        .copied()
+        .context("program has no `Main` class")
+}
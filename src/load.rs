@@ -0,0 +1,65 @@
+use crate::{parse, program::Program};
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+/// Parses the file at `path` along with everything it imports, directly or
+/// indirectly, into a single program without any imports left.
+pub fn program(path: &Path) -> Result<Program> {
+    let mut loader = Loader::default();
+    loader.load(path)?;
+    Ok(Program {
+        imports: Vec::new(),
+        classes: loader.classes,
+    })
+}
+
+#[derive(Default)]
+struct Loader {
+    classes: Vec<crate::program::Class>,
+    loaded: HashSet<PathBuf>,
+    /// The chain of files currently being loaded, used to detect cycles.
+    loading: Vec<PathBuf>,
+}
+
+impl Loader {
+    fn load(&mut self, path: &Path) -> Result<()> {
+        let canonical_path = path
+            .canonicalize()
+            .with_context(|| format!("failed to read `{}`", path.display()))?;
+        if self.loading.contains(&canonical_path) {
+            let cycle = self
+                .loading
+                .iter()
+                .skip_while(|loading| **loading != canonical_path)
+                .chain(std::iter::once(&canonical_path))
+                .map(|path| format!("`{}`", path.display()))
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            bail!("import cycle: {cycle}");
+        }
+        if !self.loaded.insert(canonical_path.clone()) {
+            return Ok(());
+        }
+
+        let source_code = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read `{}`", path.display()))?;
+        let program = parse::program(&source_code)
+            .with_context(|| format!("syntax error in `{}`", path.display()))?;
+
+        // Imports are relative to the directory of the importing file.
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        self.loading.push(canonical_path);
+        let result = program
+            .imports
+            .iter()
+            .try_for_each(|import| self.load(&directory.join(import)));
+        self.loading.pop();
+        result?;
+
+        self.classes.extend(program.classes);
+        Ok(())
+    }
+}
@@ -1,32 +1,211 @@
 #![forbid(unsafe_code, clippy::unwrap_used)]
 #![warn(clippy::nursery, clippy::pedantic)]
 
-mod expression;
-mod method;
-mod object;
-mod parse;
-mod program;
-mod resolve;
-mod typ;
-mod value;
-mod vm;
-
-use anyhow::{ensure, Context, Result};
-
-fn main() -> Result<()> {
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use ol::{
+    bundle, load, main_class, method, parse, repl, resolve, value::Value, vm,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+
+const USAGE: &str = "\
+usage: ol [options] [<file> [<arguments>...]]
+
+Starts a REPL when no file is given.
+
+options:
+        --check           parse and resolve <file> without running it
+        --dump-ast        print the resolved syntax tree of <file>
+        --emit-tokens     print the tokens of <file> without parsing it
+        --bundle <out>    compile <file> into a bundle at <out>
+        --run-bundle      run <file> as a bundle created by `--bundle`
+        --time            print how long each phase took to stderr
+        --warn-shadow     warn when a `let` or `var` shadows another variable
+    -h, --help            print this help
+    -V, --version         print version information";
+
+fn main() -> ExitCode {
+    match real_main() {
+        Ok(exit_code) => exit_code,
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn real_main() -> Result<ExitCode> {
     let mut args = std::env::args_os().skip(1);
-    ensure!(args.len() < 2, "too many command line arguments");
-    let source_path = args.next().context("no file provided")?;
-    let source_code = std::fs::read_to_string(source_path)
-        .context("failed to read source file")?;
-    let program = parse::program(&source_code).context("syntax error")?;
+    let mut bundle_path = None;
+    let mut is_bundle = false;
+    let mut is_check = false;
+    let mut is_dump_ast = false;
+    let mut is_emit_tokens = false;
+    let mut is_time = false;
+    let mut warnings = resolve::Warnings::default();
+    let path = loop {
+        let Some(arg) = args.next() else {
+            ensure!(
+                bundle_path.is_none()
+                    && !is_bundle
+                    && !is_check
+                    && !is_dump_ast
+                    && !is_emit_tokens
+                    && !is_time
+                    && !warnings.shadowing,
+                "no file provided"
+            );
+            repl::run()?;
+            return Ok(ExitCode::SUCCESS);
+        };
+        match arg.to_str() {
+            Some("--bundle") => {
+                bundle_path = Some(
+                    args.next()
+                        .context("`--bundle` requires an output path")?,
+                );
+            }
+            Some("--run-bundle") => is_bundle = true,
+            Some("--check") => is_check = true,
+            Some("--dump-ast") => is_dump_ast = true,
+            Some("--emit-tokens") => is_emit_tokens = true,
+            Some("--time") => is_time = true,
+            Some("--warn-shadow") => warnings.shadowing = true,
+            Some("--help" | "-h") => {
+                println!("{USAGE}");
+                return Ok(ExitCode::SUCCESS);
+            }
+            Some("--version" | "-V") => {
+                print_version();
+                return Ok(ExitCode::SUCCESS);
+            }
+            Some(flag) if flag.starts_with("--") => {
+                bail!("unknown flag `{flag}`\n\n{USAGE}");
+            }
+            _ => break PathBuf::from(arg),
+        }
+    };
+
+    if is_emit_tokens {
+        emit_tokens(&path)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut timings = Timings::default();
+    let program = if is_bundle {
+        timings.time("read", || bundle::read(&path))?
+    } else {
+        let program = timings.time("parse", || load::program(&path))?;
+        let program = timings
+            .time("resolve", || resolve::program(program, &mut warnings));
+        for warning in &warnings.found {
+            eprintln!("warning: {warning}");
+        }
+        program?
+    };
+
+    if is_check {
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if is_dump_ast {
+        println!("{program:#?}");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(bundle_path) = bundle_path {
+        ensure!(
+            args.len() == 0,
+            "program arguments cannot be stored in a bundle"
+        );
+        bundle::write(bundle_path.as_ref(), &program)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let program_arguments = args
+        .map(|arg| {
+            arg.into_string().map_err(|arg| {
+                anyhow!(
+                    "command line argument `{}` is not valid UTF-8",
+                    arg.to_string_lossy()
+                )
+            })
+        })
+        .collect::<Result<_>>()?;
     let mut vm = vm::VM::new();
-    let class_ids = vm.load_program(program)?;
-    vm.run(
-        *class_ids
-            .get("Main")
-            .context("program has no `Main` class")?,
-    )?;
+    vm.set_program_arguments(program_arguments);
+    let class_ids = timings.time("load", || vm.load_program(program))?;
+    let main_class = main_class(&class_ids)?;
+    let result = timings.time("run", || vm.run(main_class));
+    if is_time {
+        eprintln!("{timings}");
+    }
 
+    exit_code(&result?)
+}
+
+/// Wall-clock time spent in each phase, for `--time`.
+#[derive(Default)]
+struct Timings(Vec<(&'static str, Duration)>);
+
+impl Timings {
+    fn time<T>(&mut self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.0.push((phase, start.elapsed()));
+        result
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (phase, duration)) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{phase}: {:.1}ms", duration.as_secs_f64() * 1000.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Only looks at `path` itself, not at the files it imports.
+fn emit_tokens(path: &Path) -> Result<()> {
+    let source_code = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read `{}`", path.display()))?;
+    let tokens = parse::tokens(&source_code)
+        .with_context(|| format!("syntax error in `{}`", path.display()))?;
+    for token in tokens {
+        let parse::Token { kind, text, span } = token;
+        println!("{}..{} {kind:?} {text}", span.start, span.end);
+    }
     Ok(())
 }
+
+fn print_version() {
+    let methods = method::default_methods();
+    let method_count = methods.values().map(HashMap::len).sum::<usize>()
+        + method::object_methods().len()
+        + method::universal_methods().len();
+    println!(
+        "ol {} ({} builtin types, {method_count} builtin methods)",
+        env!("CARGO_PKG_VERSION"),
+        methods.len()
+    );
+}
+
+/// `main` may return an `I32` to set the exit code of the process. Any other
+/// value is ignored.
+fn exit_code(result: &Value) -> Result<ExitCode> {
+    match result {
+        Value::I32(code) => u8::try_from(*code)
+            .map(ExitCode::from)
+            .map_err(|_| anyhow!("exit code {code} is out of range")),
+        _ => Ok(ExitCode::SUCCESS),
+    }
+}
@@ -1,41 +1,831 @@
 use crate::{expression::Expression, typ::Type, value::Value, vm::VM};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use std::{collections::HashMap, rc::Rc};
 
-type BuiltinMethod = fn(&mut VM, &Value, &[Value]) -> Value;
+type BuiltinMethod = fn(&mut VM, &Value, &[Value]) -> Result<Value>;
 
 pub enum Method {
     Builtin(BuiltinMethod),
-    Custom { body: Expression },
+    Custom {
+        parameter_count: usize,
+        body: Expression,
+    },
 }
 
 pub fn default_methods() -> HashMap<Type, HashMap<String, Rc<Method>>> {
-    HashMap::from([(
-        Type::String,
-        HashMap::from([
-            (
-                "println".to_owned(),
-                Rc::new(Method::Builtin(|_vm, this, _arguments| {
-                    let Value::String(this) = this else { todo!() };
-                    println!("{this}");
-                    Value::Unit
-                })),
-            ),
-            (
-                "concat".to_owned(),
-                Rc::new(Method::Builtin(|_vm, this, arguments| {
-                    let Value::String(this) = this else { todo!() };
-                    Value::String(
-                        std::iter::once(&**this)
-                            .chain(arguments.iter().map(|argument| {
-                                match argument {
-                                    Value::String(argument) => &**argument,
-                                    _ => todo!(),
-                                }
-                            }))
-                            .collect::<String>(),
-                    )
-                })),
-            ),
-        ]),
-    )])
+    HashMap::from([
+        (Type::Unit, unit_methods()),
+        (Type::Bool, bool_methods()),
+        (Type::I32, i32_methods()),
+        (Type::I64, i64_methods()),
+        (Type::F64, f64_methods()),
+        (Type::String, string_methods()),
+        (Type::List, list_methods()),
+        (Type::Map, map_methods()),
+        (Type::Closure, closure_methods()),
+    ])
+}
+
+/// Whether the builtin method `name` of `typ` only computes its result from
+/// its receiver and arguments, so that calling it on literals can be done
+/// ahead of time.
+pub fn is_pure(typ: Type, name: &str) -> bool {
+    matches!(typ, Type::I32 | Type::I64 | Type::F64)
+        && matches!(
+            name,
+            "add"
+                | "sub"
+                | "mul"
+                | "div"
+                | "rem"
+                | "wrapping_add"
+                | "wrapping_sub"
+                | "wrapping_mul"
+                | "bitand"
+                | "bitor"
+                | "bitxor"
+                | "bitnot"
+                | "shl"
+                | "shr"
+                | "lt"
+                | "gt"
+                | "le"
+                | "ge"
+                | "neg"
+                | "abs"
+                | "min"
+                | "max"
+                | "clamp"
+                | "sqrt"
+                | "floor"
+                | "ceil"
+                | "round"
+                | "to_i32"
+                | "to_i64"
+                | "to_f64"
+        )
+}
+
+/// Methods that values of every type have. Methods of the type itself take
+/// precedence over these, and for objects so do `object_methods`.
+pub fn universal_methods() -> HashMap<String, Rc<Method>> {
+    builtins([
+        ("eq", eq),
+        ("ne", ne),
+        ("assert_eq", assert_eq),
+        ("to_string", |vm, this, _arguments| {
+            let string = this.with_class_names(vm.class_names()).to_string();
+            Ok(Value::String(string.into()))
+        }),
+        ("debug", |vm, this, _arguments| {
+            let string = this.dump(vm.class_names()).to_string();
+            Ok(Value::String(string.into()))
+        }),
+        ("typeof", |vm, this, _arguments| {
+            let typ = this.typ().with_class_names(vm.class_names()).to_string();
+            Ok(Value::String(typ.into()))
+        }),
+    ])
+}
+
+fn eq(vm: &mut VM, this: &Value, arguments: &[Value]) -> Result<Value> {
+    let [other] = exactly(arguments)?;
+    ensure!(
+        this.typ() == other.typ(),
+        "cannot compare `{}` with `{}`",
+        this.typ().with_class_names(vm.class_names()),
+        other.typ().with_class_names(vm.class_names())
+    );
+    Ok(Value::Bool(this == other))
+}
+
+fn ne(vm: &mut VM, this: &Value, arguments: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(!eq(vm, this, arguments)?.as_bool()?))
+}
+
+fn assert_eq(vm: &mut VM, this: &Value, arguments: &[Value]) -> Result<Value> {
+    let [other] = exactly(arguments)?;
+    ensure!(
+        eq(vm, this, arguments)?.as_bool()?,
+        "assertion failed: `{}` is not equal to `{}`",
+        this.with_class_names(vm.class_names()),
+        other.with_class_names(vm.class_names())
+    );
+    Ok(Value::Unit)
+}
+
+/// Methods that every object has unless its class defines a method with the
+/// same name.
+pub fn object_methods() -> HashMap<String, Rc<Method>> {
+    builtins([
+        ("fields", |_vm, this, _arguments| {
+            let object = this.as_object()?;
+            let mut names = object.properties.keys().collect::<Vec<_>>();
+            // Property order would otherwise depend on the hash map.
+            names.sort_unstable();
+            Ok(Value::List(Rc::new(
+                names
+                    .into_iter()
+                    .map(|name| Value::String(name.as_str().into()))
+                    .collect(),
+            )))
+        }),
+        ("get", |_vm, this, arguments| {
+            let [name] = exactly(arguments)?;
+            let name = name.as_str()?;
+            this.as_object()?
+                .properties
+                .get(name)
+                .cloned()
+                .with_context(|| format!("object has no field `{name}`"))
+        }),
+    ])
+}
+
+fn unit_methods() -> HashMap<String, Rc<Method>> {
+    builtins([
+        ("args", |vm, _this, _arguments| {
+            Ok(Value::List(vm.program_arguments()))
+        }),
+        // There is no literal syntax for maps since it would look just like a
+        // block.
+        ("map", |_vm, _this, _arguments| {
+            Ok(Value::Map(Rc::default()))
+        }),
+    ])
+}
+
+#[allow(clippy::too_many_lines)]
+fn i32_methods() -> HashMap<String, Rc<Method>> {
+    builtins([
+        ("add", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::I32(
+                this.as_i32()?
+                    .checked_add(rhs.as_i32()?)
+                    .context("I32 overflow in add")?,
+            ))
+        }),
+        ("sub", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::I32(
+                this.as_i32()?
+                    .checked_sub(rhs.as_i32()?)
+                    .context("I32 overflow in sub")?,
+            ))
+        }),
+        ("mul", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::I32(
+                this.as_i32()?
+                    .checked_mul(rhs.as_i32()?)
+                    .context("I32 overflow in mul")?,
+            ))
+        }),
+        // For when wraparound is what's wanted, as in hashing.
+        ("wrapping_add", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::I32(this.as_i32()?.wrapping_add(rhs.as_i32()?)))
+        }),
+        ("wrapping_sub", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::I32(this.as_i32()?.wrapping_sub(rhs.as_i32()?)))
+        }),
+        ("wrapping_mul", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::I32(this.as_i32()?.wrapping_mul(rhs.as_i32()?)))
+        }),
+        ("div", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            let (lhs, rhs) = (this.as_i32()?, rhs.as_i32()?);
+            ensure!(rhs != 0, "division by zero");
+            Ok(Value::I32(
+                lhs.checked_div(rhs).context("I32 overflow in div")?,
+            ))
+        }),
+        ("rem", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            let (lhs, rhs) = (this.as_i32()?, rhs.as_i32()?);
+            ensure!(rhs != 0, "division by zero");
+            Ok(Value::I32(
+                lhs.checked_rem(rhs).context("I32 overflow in rem")?,
+            ))
+        }),
+        ("bitand", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::I32(this.as_i32()? & rhs.as_i32()?))
+        }),
+        ("bitor", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::I32(this.as_i32()? | rhs.as_i32()?))
+        }),
+        ("bitxor", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::I32(this.as_i32()? ^ rhs.as_i32()?))
+        }),
+        ("bitnot", |_vm, this, _arguments| {
+            Ok(Value::I32(!this.as_i32()?))
+        }),
+        ("shl", |_vm, this, arguments| {
+            let [amount] = exactly(arguments)?;
+            Ok(Value::I32(this.as_i32()? << shift_amount(amount)?))
+        }),
+        // Arithmetic shift, so the sign bit is kept.
+        ("shr", |_vm, this, arguments| {
+            let [amount] = exactly(arguments)?;
+            Ok(Value::I32(this.as_i32()? >> shift_amount(amount)?))
+        }),
+        ("lt", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::Bool(this.as_i32()? < rhs.as_i32()?))
+        }),
+        ("gt", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::Bool(this.as_i32()? > rhs.as_i32()?))
+        }),
+        ("le", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::Bool(this.as_i32()? <= rhs.as_i32()?))
+        }),
+        ("ge", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::Bool(this.as_i32()? >= rhs.as_i32()?))
+        }),
+        ("neg", |_vm, this, _arguments| {
+            Ok(Value::I32(
+                this.as_i32()?
+                    .checked_neg()
+                    .context("I32 overflow in neg")?,
+            ))
+        }),
+        ("abs", |_vm, this, _arguments| {
+            Ok(Value::I32(
+                this.as_i32()?
+                    .checked_abs()
+                    .context("I32 overflow in abs")?,
+            ))
+        }),
+        ("min", |_vm, this, arguments| {
+            let [other] = exactly(arguments)?;
+            Ok(Value::I32(this.as_i32()?.min(other.as_i32()?)))
+        }),
+        ("max", |_vm, this, arguments| {
+            let [other] = exactly(arguments)?;
+            Ok(Value::I32(this.as_i32()?.max(other.as_i32()?)))
+        }),
+        // From `this` up to but not including the argument, so `range 5 2`
+        // is empty rather than counting down.
+        ("range", |_vm, this, arguments| {
+            let [end] = exactly(arguments)?;
+            Ok(Value::List(Rc::new(
+                (this.as_i32()?..end.as_i32()?).map(Value::I32).collect(),
+            )))
+        }),
+        // Calls the closure with each index from 0 up to the count. A negative
+        // count is an error just like for `repeat` on strings.
+        ("times", |vm, this, arguments| {
+            let [closure] = exactly(arguments)?;
+            let count = this.as_i32()?;
+            ensure!(count >= 0, "cannot repeat a closure {count} times");
+            let closure = closure.as_closure()?;
+            for i in 0..count {
+                vm.call_closure(closure, vec![Value::I32(i)])?;
+            }
+            Ok(Value::Unit)
+        }),
+        ("to_i64", |_vm, this, _arguments| {
+            Ok(Value::I64(this.as_i32()?.into()))
+        }),
+        ("to_f64", |_vm, this, _arguments| {
+            Ok(Value::F64(this.as_i32()?.into()))
+        }),
+        ("clamp", |_vm, this, arguments| {
+            let this = this.as_i32()?;
+            let [lo, hi] = exactly(arguments)?;
+            let (lo, hi) = (lo.as_i32()?, hi.as_i32()?);
+            ensure!(
+                lo <= hi,
+                "lower bound {lo} of `clamp` is greater than upper bound {hi}"
+            );
+            Ok(Value::I32(this.clamp(lo, hi)))
+        }),
+    ])
+}
+
+/// Mirrors the arithmetic and comparisons of `I32`.
+fn i64_methods() -> HashMap<String, Rc<Method>> {
+    builtins([
+        ("add", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::I64(
+                this.as_i64()?
+                    .checked_add(rhs.as_i64()?)
+                    .context("I64 overflow in add")?,
+            ))
+        }),
+        ("sub", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::I64(
+                this.as_i64()?
+                    .checked_sub(rhs.as_i64()?)
+                    .context("I64 overflow in sub")?,
+            ))
+        }),
+        ("mul", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::I64(
+                this.as_i64()?
+                    .checked_mul(rhs.as_i64()?)
+                    .context("I64 overflow in mul")?,
+            ))
+        }),
+        ("div", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            let (lhs, rhs) = (this.as_i64()?, rhs.as_i64()?);
+            ensure!(rhs != 0, "division by zero");
+            Ok(Value::I64(
+                lhs.checked_div(rhs).context("I64 overflow in div")?,
+            ))
+        }),
+        ("rem", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            let (lhs, rhs) = (this.as_i64()?, rhs.as_i64()?);
+            ensure!(rhs != 0, "division by zero");
+            Ok(Value::I64(
+                lhs.checked_rem(rhs).context("I64 overflow in rem")?,
+            ))
+        }),
+        ("lt", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::Bool(this.as_i64()? < rhs.as_i64()?))
+        }),
+        ("gt", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::Bool(this.as_i64()? > rhs.as_i64()?))
+        }),
+        ("le", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::Bool(this.as_i64()? <= rhs.as_i64()?))
+        }),
+        ("ge", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::Bool(this.as_i64()? >= rhs.as_i64()?))
+        }),
+        ("neg", |_vm, this, _arguments| {
+            Ok(Value::I64(
+                this.as_i64()?
+                    .checked_neg()
+                    .context("I64 overflow in neg")?,
+            ))
+        }),
+        ("abs", |_vm, this, _arguments| {
+            Ok(Value::I64(
+                this.as_i64()?
+                    .checked_abs()
+                    .context("I64 overflow in abs")?,
+            ))
+        }),
+        ("min", |_vm, this, arguments| {
+            let [other] = exactly(arguments)?;
+            Ok(Value::I64(this.as_i64()?.min(other.as_i64()?)))
+        }),
+        ("max", |_vm, this, arguments| {
+            let [other] = exactly(arguments)?;
+            Ok(Value::I64(this.as_i64()?.max(other.as_i64()?)))
+        }),
+        ("to_i32", |_vm, this, _arguments| {
+            let this = this.as_i64()?;
+            i32::try_from(this)
+                .map(Value::I32)
+                .map_err(|_| anyhow!("{this} is out of range for I32"))
+        }),
+    ])
+}
+
+/// Shifting an `I32` by 32 or more bits is an error rather than wrapping the
+/// amount around like the hardware would.
+fn shift_amount(amount: &Value) -> Result<u32> {
+    let amount = amount.as_i32()?;
+    u32::try_from(amount)
+        .ok()
+        .filter(|&amount| amount < i32::BITS)
+        .with_context(|| format!("cannot shift an I32 by {amount} bits"))
+}
+
+fn bool_methods() -> HashMap<String, Rc<Method>> {
+    builtins([
+        ("not", |_vm, this, _arguments| {
+            Ok(Value::Bool(!this.as_bool()?))
+        }),
+        ("assert", |_vm, this, _arguments| {
+            ensure!(this.as_bool()?, "assertion failed");
+            Ok(Value::Unit)
+        }),
+    ])
+}
+
+/// Arithmetic follows IEEE 754, so unlike `I32.div`, dividing by zero is not
+/// an error but produces an infinity, or NaN for `0.0 / 0.0`.
+fn f64_methods() -> HashMap<String, Rc<Method>> {
+    builtins([
+        ("add", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::F64(this.as_f64()? + rhs.as_f64()?))
+        }),
+        ("sub", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::F64(this.as_f64()? - rhs.as_f64()?))
+        }),
+        ("mul", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::F64(this.as_f64()? * rhs.as_f64()?))
+        }),
+        ("div", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::F64(this.as_f64()? / rhs.as_f64()?))
+        }),
+        ("lt", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::Bool(this.as_f64()? < rhs.as_f64()?))
+        }),
+        ("gt", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::Bool(this.as_f64()? > rhs.as_f64()?))
+        }),
+        ("le", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::Bool(this.as_f64()? <= rhs.as_f64()?))
+        }),
+        ("ge", |_vm, this, arguments| {
+            let [rhs] = exactly(arguments)?;
+            Ok(Value::Bool(this.as_f64()? >= rhs.as_f64()?))
+        }),
+        ("sqrt", |_vm, this, _arguments| {
+            Ok(Value::F64(this.as_f64()?.sqrt()))
+        }),
+        ("abs", |_vm, this, _arguments| {
+            Ok(Value::F64(this.as_f64()?.abs()))
+        }),
+        ("floor", |_vm, this, _arguments| {
+            Ok(Value::F64(this.as_f64()?.floor()))
+        }),
+        ("ceil", |_vm, this, _arguments| {
+            Ok(Value::F64(this.as_f64()?.ceil()))
+        }),
+        // Halfway cases round away from zero.
+        ("round", |_vm, this, _arguments| {
+            Ok(Value::F64(this.as_f64()?.round()))
+        }),
+        ("neg", |_vm, this, _arguments| {
+            Ok(Value::F64(-this.as_f64()?))
+        }),
+        ("clamp", |_vm, this, arguments| {
+            let this = this.as_f64()?;
+            let [lo, hi] = exactly(arguments)?;
+            let (lo, hi) = (lo.as_f64()?, hi.as_f64()?);
+            // This also rejects NaN bounds, which `f64::clamp` would panic on.
+            // A NaN receiver is returned unchanged.
+            ensure!(
+                lo <= hi,
+                "lower bound {lo:?} of `clamp` is not less than or equal to \
+                 upper bound {hi:?}"
+            );
+            Ok(Value::F64(this.clamp(lo, hi)))
+        }),
+        ("to_i32", |_vm, this, _arguments| f64_to_i32(this.as_f64()?)),
+    ])
+}
+
+/// Converts by truncating towards zero, so `2.9` becomes `2` and `-2.9`
+/// becomes `-2`. Unlike an `as` cast, NaN, infinities and values outside the
+/// range of `I32` are errors instead of being saturated.
+fn f64_to_i32(this: f64) -> Result<Value> {
+    let truncated = this.trunc();
+    ensure!(
+        (f64::from(i32::MIN)..=f64::from(i32::MAX)).contains(&truncated),
+        "{this:?} is out of range for I32"
+    );
+    #[allow(clippy::cast_possible_truncation)]
+    Ok(Value::I32(truncated as i32))
+}
+
+fn string_methods() -> HashMap<String, Rc<Method>> {
+    builtins([
+        ("println", |vm, this, _arguments| {
+            let this = this.as_str()?;
+            writeln!(vm.output(), "{this}")?;
+            Ok(Value::Unit)
+        }),
+        ("print", |vm, this, _arguments| {
+            let this = this.as_str()?;
+            write!(vm.output(), "{this}")?;
+            vm.output().flush()?;
+            Ok(Value::Unit)
+        }),
+        ("eprintln", |vm, this, _arguments| {
+            let this = this.as_str()?;
+            writeln!(vm.error_output(), "{this}")?;
+            Ok(Value::Unit)
+        }),
+        ("eprint", |vm, this, _arguments| {
+            let this = this.as_str()?;
+            write!(vm.error_output(), "{this}")?;
+            Ok(Value::Unit)
+        }),
+        ("read_file", read_file),
+        ("write_file", write_file),
+        // Aborts the program with the string as the error message.
+        ("error", |_vm, this, _arguments| bail!("{}", this.as_str()?)),
+        // Arguments other than strings are converted like with `to_string`.
+        ("concat", |vm, this, arguments| {
+            let mut result = this.as_str()?.to_owned();
+            for argument in arguments {
+                result.push_str(
+                    &argument.with_class_names(vm.class_names()).to_string(),
+                );
+            }
+            Ok(Value::String(result.into()))
+        }),
+        ("reverse", |_vm, this, _arguments| {
+            Ok(Value::String(reverse(this.as_str()?).into()))
+        }),
+        ("repeat", |_vm, this, arguments| {
+            let [count] = exactly(arguments)?;
+            let count = count.as_i32()?;
+            let count = usize::try_from(count)
+                .map_err(|_| anyhow!("cannot repeat a string {count} times"))?;
+            Ok(Value::String(this.as_str()?.repeat(count).into()))
+        }),
+        // These are full Unicode case mappings rather than ASCII-only ones, so
+        // the length can change: `to_upper "ß"` is `"SS"`.
+        ("to_upper", |_vm, this, _arguments| {
+            Ok(Value::String(this.as_str()?.to_uppercase().into()))
+        }),
+        ("to_lower", |_vm, this, _arguments| {
+            Ok(Value::String(this.as_str()?.to_lowercase().into()))
+        }),
+        ("contains", |_vm, this, arguments| {
+            let [needle] = exactly(arguments)?;
+            Ok(Value::Bool(this.as_str()?.contains(needle.as_str()?)))
+        }),
+        ("starts_with", |_vm, this, arguments| {
+            let [prefix] = exactly(arguments)?;
+            Ok(Value::Bool(this.as_str()?.starts_with(prefix.as_str()?)))
+        }),
+        ("ends_with", |_vm, this, arguments| {
+            let [suffix] = exactly(arguments)?;
+            Ok(Value::Bool(this.as_str()?.ends_with(suffix.as_str()?)))
+        }),
+        ("trim", |_vm, this, _arguments| {
+            Ok(Value::String(this.as_str()?.trim().into()))
+        }),
+        ("trim_start", |_vm, this, _arguments| {
+            Ok(Value::String(this.as_str()?.trim_start().into()))
+        }),
+        ("trim_end", |_vm, this, _arguments| {
+            Ok(Value::String(this.as_str()?.trim_end().into()))
+        }),
+        ("is_empty", |_vm, this, _arguments| {
+            Ok(Value::Bool(this.as_str()?.is_empty()))
+        }),
+        ("at", at),
+        // Unicode scalar values, like `at`.
+        ("chars", |_vm, this, _arguments| {
+            Ok(Value::List(Rc::new(
+                this.as_str()?
+                    .chars()
+                    .map(|c| Value::String(c.to_string().into()))
+                    .collect(),
+            )))
+        }),
+        // Like Rust's `str::lines`: a trailing `\r` is removed from each line
+        // and a final newline doesn't start another line.
+        ("lines", |_vm, this, _arguments| {
+            Ok(Value::List(Rc::new(
+                this.as_str()?
+                    .lines()
+                    .map(|line| Value::String(line.into()))
+                    .collect(),
+            )))
+        }),
+        ("index_of", index_of),
+        ("substring", substring),
+        // Surrounding whitespace is an error rather than silently ignored;
+        // use `trim` first if that is intended.
+        ("parse_i32", |_vm, this, _arguments| {
+            let this = this.as_str()?;
+            this.parse()
+                .map(Value::I32)
+                .with_context(|| format!("cannot parse {this:?} as I32"))
+        }),
+    ])
+}
+
+/// There is no character type, so this returns a string of the single Unicode
+/// scalar value at the given index.
+fn at(_vm: &mut VM, this: &Value, arguments: &[Value]) -> Result<Value> {
+    let [index] = exactly(arguments)?;
+    let index = index.as_i32()?;
+    let this = this.as_str()?;
+    usize::try_from(index)
+        .ok()
+        .and_then(|index| this.chars().nth(index))
+        .map(|c| Value::String(c.to_string().into()))
+        .with_context(|| format!("index {index} is out of range for {this:?}"))
+}
+
+/// Indices are in Unicode scalar values like for `at`, and -1 means that the
+/// needle does not occur at all.
+fn index_of(_vm: &mut VM, this: &Value, arguments: &[Value]) -> Result<Value> {
+    let [needle] = exactly(arguments)?;
+    let this = this.as_str()?;
+    let Some(byte_index) = this.find(needle.as_str()?) else {
+        return Ok(Value::I32(-1));
+    };
+    let index = this[..byte_index].chars().count();
+    Ok(Value::I32(
+        i32::try_from(index).context("string index overflows I32")?,
+    ))
+}
+
+/// The string is the path of the file to read.
+fn read_file(vm: &mut VM, this: &Value, _arguments: &[Value]) -> Result<Value> {
+    let path = this.as_str()?;
+    ensure!(!vm.is_sandboxed(), "cannot read files in a sandbox");
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read `{path}`"))?;
+    Ok(Value::String(contents.into()))
+}
+
+/// The string is the path of the file, which is created if it doesn't exist
+/// and truncated otherwise. Missing parent directories are an error rather
+/// than being created.
+fn write_file(vm: &mut VM, this: &Value, arguments: &[Value]) -> Result<Value> {
+    let [contents] = exactly(arguments)?;
+    let path = this.as_str()?;
+    let contents = contents.as_str()?;
+    ensure!(!vm.is_sandboxed(), "cannot write files in a sandbox");
+    std::fs::write(path, contents)
+        .with_context(|| format!("failed to write `{path}`"))?;
+    Ok(Value::Unit)
+}
+
+/// Takes the start and length of the substring, both in Unicode scalar values.
+fn substring(_vm: &mut VM, this: &Value, arguments: &[Value]) -> Result<Value> {
+    let [start, length] = exactly(arguments)?;
+    let (start, length) = (start.as_i32()?, length.as_i32()?);
+    let this = this.as_str()?;
+    let out_of_range = || {
+        anyhow!(
+            "substring of length {length} at {start} is out of range for \
+             {this:?}"
+        )
+    };
+    let start_index = usize::try_from(start).map_err(|_| out_of_range())?;
+    let length = usize::try_from(length).map_err(|_| out_of_range())?;
+    let mut char_indices = this
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(this.len()));
+    let begin = char_indices.nth(start_index).ok_or_else(out_of_range)?;
+    let end = if length == 0 {
+        begin
+    } else {
+        char_indices.nth(length - 1).ok_or_else(out_of_range)?
+    };
+    Ok(Value::String(this[begin..end].into()))
+}
+
+/// Reverses by Unicode scalar values, so multi-byte characters stay intact.
+/// Combining characters still end up attached to the wrong base character
+/// unless the `graphemes` feature is enabled, which reverses by extended
+/// grapheme clusters instead.
+fn reverse(s: &str) -> String {
+    #[cfg(feature = "graphemes")]
+    {
+        use unicode_segmentation::UnicodeSegmentation;
+        s.graphemes(true).rev().collect()
+    }
+    #[cfg(not(feature = "graphemes"))]
+    {
+        s.chars().rev().collect()
+    }
+}
+
+fn list_methods() -> HashMap<String, Rc<Method>> {
+    builtins([
+        ("is_empty", |_vm, this, _arguments| {
+            Ok(Value::Bool(this.as_list()?.is_empty()))
+        }),
+        ("map", |vm, this, arguments| {
+            let [closure] = exactly(arguments)?;
+            let closure = closure.as_closure()?;
+            Ok(Value::List(Rc::new(
+                this.as_list()?
+                    .iter()
+                    .map(|element| {
+                        vm.call_closure(closure, vec![element.clone()])
+                    })
+                    .collect::<Result<_>>()?,
+            )))
+        }),
+        ("join", |_vm, this, arguments| {
+            let [separator] = exactly(arguments)?;
+            let separator = separator.as_str()?;
+            let elements = this
+                .as_list()?
+                .iter()
+                .map(Value::as_str)
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Value::String(elements.join(separator).into()))
+        }),
+        ("filter", |vm, this, arguments| {
+            let [closure] = exactly(arguments)?;
+            let closure = closure.as_closure()?;
+            let mut elements = Vec::new();
+            for element in this.as_list()?.iter() {
+                if vm.call_closure(closure, vec![element.clone()])?.as_bool()? {
+                    elements.push(element.clone());
+                }
+            }
+            Ok(Value::List(Rc::new(elements)))
+        }),
+        // The closure takes the accumulator followed by the element.
+        ("fold", |vm, this, arguments| {
+            let [initial, closure] = exactly(arguments)?;
+            let closure = closure.as_closure()?;
+            this.as_list()?.iter().try_fold(
+                initial.clone(),
+                |accumulator, element| {
+                    vm.call_closure(closure, vec![accumulator, element.clone()])
+                },
+            )
+        }),
+    ])
+}
+
+fn map_methods() -> HashMap<String, Rc<Method>> {
+    builtins([
+        ("insert", |_vm, this, arguments| {
+            let [key, value] = exactly(arguments)?;
+            this.as_map()?
+                .borrow_mut()
+                .insert(key.as_str()?.to_owned(), value.clone());
+            Ok(Value::Unit)
+        }),
+        ("get", |_vm, this, arguments| {
+            let [key] = exactly(arguments)?;
+            let key = key.as_str()?;
+            this.as_map()?
+                .borrow()
+                .get(key)
+                .cloned()
+                .with_context(|| format!("map has no key {key:?}"))
+        }),
+        ("contains", |_vm, this, arguments| {
+            let [key] = exactly(arguments)?;
+            Ok(Value::Bool(
+                this.as_map()?.borrow().contains_key(key.as_str()?),
+            ))
+        }),
+        ("remove", |_vm, this, arguments| {
+            let [key] = exactly(arguments)?;
+            let key = key.as_str()?;
+            this.as_map()?
+                .borrow_mut()
+                .remove(key)
+                .with_context(|| format!("map has no key {key:?}"))
+        }),
+        ("keys", |_vm, this, _arguments| {
+            let entries = this.as_map()?.borrow();
+            let mut keys = entries.keys().collect::<Vec<_>>();
+            keys.sort_unstable();
+            Ok(Value::List(Rc::new(
+                keys.into_iter()
+                    .map(|key| Value::String(key.as_str().into()))
+                    .collect(),
+            )))
+        }),
+    ])
+}
+
+fn closure_methods() -> HashMap<String, Rc<Method>> {
+    builtins([("apply", |vm, this, arguments| {
+        vm.call_closure(this.as_closure()?, arguments.to_vec())
+    })])
+}
+
+fn builtins<const N: usize>(
+    methods: [(&str, BuiltinMethod); N],
+) -> HashMap<String, Rc<Method>> {
+    methods
+        .into_iter()
+        .map(|(name, f)| (name.to_owned(), Rc::new(Method::Builtin(f))))
+        .collect()
+}
+
+fn exactly<const N: usize>(arguments: &[Value]) -> Result<&[Value; N]> {
+    arguments
+        .try_into()
+        .map_err(|_| anyhow!("expected {N} arguments, got {}", arguments.len()))
 }
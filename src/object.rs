@@ -1,7 +1,8 @@
 use crate::{value::Value, vm::ClassID};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Object {
     pub class: ClassID,
     pub properties: HashMap<String, Value>,
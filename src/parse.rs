@@ -1,54 +1,374 @@
 use crate::{
-    program::{Class, ClassMethod, Program},
+    program::{Class, ClassMethod, Field, Program},
     value::Value,
 };
-use std::borrow::Cow;
+use std::{borrow::Cow, fmt, ops::Range, rc::Rc};
 use winnow::{
     ascii::{alpha1, alphanumeric1, digit1, multispace1},
     combinator::{
-        alt, count, delimited, not, opt, preceded, repeat0, repeat1,
-        separated0, terminated,
+        alt, count, cut_err, delimited, eof, not, opt, preceded, repeat0,
+        repeat1, separated1, success, terminated,
     },
-    error::Error,
-    stream::AsChar,
-    token::{one_of, take_till0, take_till1, take_while, take_while0},
+    error::{ErrMode, Error, ErrorKind},
+    stream::{AsChar, Stateful},
+    token::{one_of, take_till0, take_till1, take_until0, take_while},
     Parser,
 };
 
-type Input<'a> = &'a str;
+type Input<'a> = Stateful<&'a str, &'a LineStarts>;
 type IResult<'a, T> = winnow::IResult<Input<'a>, T>;
 
+/// Byte offsets of the start of each line, so that parsers can find out which
+/// line they are on without rescanning the source code.
+#[derive(Debug)]
+struct LineStarts {
+    source_len: usize,
+    starts: Vec<usize>,
+}
+
+impl LineStarts {
+    fn new(source: &str) -> Self {
+        Self {
+            source_len: source.len(),
+            starts: std::iter::once(0)
+                .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+                .collect(),
+        }
+    }
+
+    /// The 1-based line number of the byte at `offset`.
+    fn line_of(&self, offset: usize) -> usize {
+        self.starts.partition_point(|&start| start <= offset)
+    }
+}
+
+/// Points out where in the source code parsing failed.
+#[derive(Debug)]
+pub struct SyntaxError {
+    message: String,
+    line: usize,
+    column: usize,
+    line_text: String,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            message,
+            line,
+            column,
+            line_text,
+        } = self;
+        let gutter = " ".repeat(line.to_string().len());
+        let padding = " ".repeat(column - 1);
+        writeln!(f, "{message} at line {line}, column {column}:")?;
+        writeln!(f, "{gutter} |")?;
+        writeln!(f, "{line} | {line_text}")?;
+        write!(f, "{gutter} | {padding}^")
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+/// Runs `parser` on all of `source`.
+fn parse_all<'a, T>(
+    source: &'a str,
+    line_starts: &'a LineStarts,
+    mut parser: impl Parser<Input<'a>, T, Error<Input<'a>>>,
+) -> Result<T, SyntaxError> {
+    parser
+        .parse(Stateful {
+            input: source,
+            state: line_starts,
+        })
+        .map_err(|err| {
+            // The error only holds the input that is left, so its length is
+            // what tells where in the source it is.
+            let offset = source.len() - err.input.input.len();
+            let line = line_starts.line_of(offset);
+            let line_start = line_starts.starts[line - 1];
+            let line_text =
+                source[line_start..].lines().next().unwrap_or_default();
+            SyntaxError {
+                message: explain(err.input),
+                line,
+                column: source[line_start..offset].chars().count() + 1,
+                line_text: line_text.to_owned(),
+            }
+        })
+}
+
+/// Says what is wrong with the input that a parser failed at, for the few
+/// cases where there is more to say than that it is unexpected.
+fn explain(input: Input) -> String {
+    match code_point_escape.with_recognized().parse_next(input) {
+        Ok((_, (code_point, escape)))
+            if char::from_u32(code_point).is_none() =>
+        {
+            let reason = if (0xD800..=0xDFFF).contains(&code_point) {
+                "surrogate code point"
+            } else {
+                "code point out of range"
+            };
+            format!("invalid Unicode escape {escape}: {reason}")
+        }
+        _ => "unexpected input".to_owned(),
+    }
+}
+
+/// The byte offset of the current position, without consuming any input.
+fn offset(input: Input) -> IResult<usize> {
+    let offset = input.state.source_len - input.input.len();
+    success(offset).parse_next(input)
+}
+
+/// The 1-based line number of the current position, without consuming any
+/// input.
+fn line(input: Input) -> IResult<usize> {
+    let line_starts = input.state;
+    offset
+        .map(|offset| line_starts.line_of(offset))
+        .parse_next(input)
+}
+
 type Expression = crate::expression::Of<String, String>;
 
-pub fn program(input: Input) -> Result<Program, Error<String>> {
-    delimited(ws, separated0(class, ws), ws)
-        .map(|classes| Program { classes })
-        .parse(input)
-        .map_err(Error::into_owned)
+pub fn program(source: &str) -> Result<Program, SyntaxError> {
+    // Imports have to come before any classes.
+    let line_starts = LineStarts::new(source);
+    let import = preceded((keyword("import"), ws), string_literal);
+    parse_all(
+        source,
+        &line_starts,
+        delimited(
+            ws,
+            (repeat0(preceded(ws, import)), repeat0(preceded(ws, class))),
+            ws,
+        )
+        .map(|(imports, classes)| Program { imports, classes }),
+    )
 }
 
-fn class(input: Input) -> IResult<Class> {
-    (
-        preceded((keyword("class"), ws), identifier),
+/// A piece of source code as recognized by `tokens`.
+#[derive(Debug)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    /// Byte offsets into the source code.
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum TokenKind {
+    Keyword,
+    Identifier,
+    String,
+    Number,
+    Punctuation,
+}
+
+/// Splits `source` into tokens for debugging.
+///
+/// This uses the same building blocks as the parser but doesn't check that the
+/// tokens form a program. Like in the parser, a sign right before a digit is
+/// part of the number unless it comes right after an operand, so `x -1` has
+/// the number `-1` while `x-1` is a subtraction.
+pub fn tokens(source: &str) -> Result<Vec<Token<'_>>, SyntaxError> {
+    let line_starts = LineStarts::new(source);
+    let punctuation = alt((
+        alt(("=>", "->", "==", "!=", "<=", ">=", "?.")),
+        one_of("{}()[];,=<>+-*/%!").recognize(),
+    ));
+    let kind = alt((
+        string_literal.value(TokenKind::String),
+        alt((f64_literal.void(), i64_literal.void(), i32_literal.void()))
+            .value(TokenKind::Number),
+        identifier_or_keyword.map(|word| {
+            if is_keyword(word) {
+                TokenKind::Keyword
+            } else {
+                TokenKind::Identifier
+            }
+        }),
+        punctuation.value(TokenKind::Punctuation),
+    ));
+    let token = (offset, kind.with_recognized()).map(
+        |(start, (kind, text)): (usize, (_, &str))| {
+            (kind, start..start + text.len())
+        },
+    );
+    let spans: Vec<_> = parse_all(
+        source,
+        &line_starts,
+        preceded(ws, repeat0(terminated(token, ws))),
+    )?;
+    let mut tokens = Vec::<Token>::new();
+    for (kind, span) in spans {
+        let text = &source[span.clone()];
+        let follows_operand = tokens.last().is_some_and(|previous| {
+            previous.span.end == span.start && previous.is_operand()
+        });
+        if matches!(kind, TokenKind::Number)
+            && text.starts_with(['+', '-'])
+            && follows_operand
+        {
+            let (sign, number) = text.split_at(1);
+            tokens.push(Token {
+                kind: TokenKind::Punctuation,
+                text: sign,
+                span: span.start..span.start + 1,
+            });
+            tokens.push(Token {
+                kind,
+                text: number,
+                span: span.start + 1..span.end,
+            });
+        } else {
+            tokens.push(Token { kind, text, span });
+        }
+    }
+    Ok(tokens)
+}
+
+impl Token<'_> {
+    /// Whether the token can end an operand, after which a sign is an
+    /// operator.
+    fn is_operand(&self) -> bool {
+        match self.kind {
+            TokenKind::Identifier | TokenKind::String | TokenKind::Number => {
+                true
+            }
+            TokenKind::Keyword => matches!(self.text, "true" | "false"),
+            TokenKind::Punctuation => matches!(self.text, ")" | "]" | "}"),
+        }
+    }
+}
+
+pub fn single_expression(source: &str) -> Result<Expression, SyntaxError> {
+    let line_starts = LineStarts::new(source);
+    parse_all(source, &line_starts, delimited(ws, expression, ws))
+}
+
+/// A single line of REPL input.
+pub enum Line {
+    Class(Class),
+    /// A `let` or `var` without an `in`, which stays in scope for the rest of
+    /// the session.
+    Binding {
+        name: String,
+        is_mutable: bool,
+        bound: Expression,
+    },
+    Expression(Expression),
+}
+
+/// A line of REPL input is tried as an expression first so that a complete
+/// `let ... in ...` is not mistaken for a top-level binding.
+pub fn repl_line(source: &str) -> Result<Line, SyntaxError> {
+    let line_starts = LineStarts::new(source);
+    let binding = (
+        alt((keyword("let").value(false), keyword("var").value(true))),
+        preceded(ws, identifier),
+        preceded((ws, '=', ws), expression),
+    )
+        .map(|(is_mutable, name, bound)| Line::Binding {
+            name,
+            is_mutable,
+            bound,
+        });
+
+    parse_all(
+        source,
+        &line_starts,
         delimited(
-            (ws, '{'),
-            repeat0(preceded(ws, class_method_definition)),
-            (ws, '}'),
+            ws,
+            alt((
+                class.map(Line::Class),
+                terminated(expression, (ws, eof)).map(Line::Expression),
+                binding,
+            )),
+            ws,
         ),
     )
-        .map(|(name, methods)| Class { name, methods })
-        .parse_next(input)
 }
 
+// Once the `class` or `def` keyword has been seen there is nothing else to try
+// instead, so errors past it are reported where they happened rather than at
+// the start of the class.
+fn class(input: Input) -> IResult<Class> {
+    preceded(
+        (keyword("class"), ws),
+        cut_err((
+            identifier,
+            opt(preceded((ws, keyword("extends"), ws), identifier)),
+            delimited(
+                (ws, '{'),
+                repeat0(preceded(ws, class_member)),
+                (ws, '}'),
+            ),
+        )),
+    )
+    .map(|(name, superclass, members): (_, _, Vec<_>)| {
+        let mut fields = Vec::new();
+        let mut methods = Vec::new();
+        for member in members {
+            match member {
+                ClassMember::Field(field) => fields.push(field),
+                ClassMember::Method(method) => methods.push(method),
+            }
+        }
+        Class {
+            name,
+            superclass,
+            fields,
+            methods,
+        }
+    })
+    .parse_next(input)
+}
+
+enum ClassMember {
+    Field(Field),
+    Method(ClassMethod),
+}
+
+fn class_member(input: Input) -> IResult<ClassMember> {
+    alt((
+        field_definition.map(ClassMember::Field),
+        class_method_definition.map(ClassMember::Method),
+    ))
+    .parse_next(input)
+}
+
+fn field_definition(input: Input) -> IResult<Field> {
+    preceded(
+        (keyword("field"), ws),
+        cut_err(terminated(
+            (identifier, preceded((ws, '=', ws), expression)),
+            (ws, ';'),
+        )),
+    )
+    .map(|(name, initializer)| Field { name, initializer })
+    .parse_next(input)
+}
+
+// The body is a single expression, so the first `;` after it always ends the
+// definition: `def f = a; b;` is a syntax error rather than a method doing
+// both `a` and `b`, which is written `def f = do { a; b };`. An empty method
+// body, as in `def foo = ;`, is the same as `()`.
 fn class_method_definition(input: Input) -> IResult<ClassMethod> {
-    delimited(
+    let body = opt(expression)
+        .map(|body| body.unwrap_or(Expression::Literal(Value::Unit)));
+    preceded(
         (keyword("def"), ws),
-        (
-            identifier,
-            repeat0(preceded(ws, identifier)),
-            preceded((ws, '=', ws), expression),
-        ),
-        (ws, ';'),
+        cut_err(terminated(
+            (
+                identifier,
+                repeat0(preceded(ws, identifier)),
+                preceded((ws, '=', ws), body),
+            ),
+            (ws, ';'),
+        )),
     )
     .map(|(name, parameters, body)| ClassMethod {
         name,
@@ -59,7 +379,102 @@ fn class_method_definition(input: Input) -> IResult<ClassMethod> {
 }
 
 fn expression(input: Input) -> IResult<Expression> {
-    alt((method_call, expression_but_not_method_call)).parse_next(input)
+    alt((return_expression, assignment, comparison)).parse_next(input)
+}
+
+fn return_expression(input: Input) -> IResult<Expression> {
+    preceded((keyword("return"), ws), expression.map(Box::new))
+        .map(Expression::Return)
+        .parse_next(input)
+}
+
+// Binary operators desugar into method calls on their left operand, so `a + b`
+// is the same as `add a b`. From loosest to tightest binding:
+//
+// - `==` `!=` `<` `>` `<=` `>=` (`eq` `ne` `lt` `gt` `le` `ge`), which can't be
+//   chained
+// - `+` `-` (`add` `sub`), left-associative
+// - `*` `/` `%` (`mul` `div` `rem`), left-associative
+// - prefix `-` `!` (`neg` `not`)
+//
+// Method application binds tighter than any operator: `f x + 1` is
+// `(f x) + 1` and `-f x` is `-(f x)`.
+fn comparison(input: Input) -> IResult<Expression> {
+    let operator = alt((
+        "==".value("eq"),
+        "!=".value("ne"),
+        "<=".value("le"),
+        ">=".value("ge"),
+        '<'.value("lt"),
+        '>'.value("gt"),
+    ));
+    (sum, opt((delimited(ws, (line, operator), ws), sum)))
+        .map(|(lhs, rhs)| match rhs {
+            Some((operator, rhs)) => binary_operation(operator, lhs, rhs),
+            None => lhs,
+        })
+        .parse_next(input)
+}
+
+fn sum(input: Input) -> IResult<Expression> {
+    let operator = alt(('+'.value("add"), '-'.value("sub")));
+    (
+        product,
+        repeat0((delimited(ws, (line, operator), ws), product)),
+    )
+        .map(fold_binary_operations)
+        .parse_next(input)
+}
+
+fn product(input: Input) -> IResult<Expression> {
+    let operator = alt(('*'.value("mul"), '/'.value("div"), '%'.value("rem")));
+    (unary, repeat0((delimited(ws, (line, operator), ws), unary)))
+        .map(fold_binary_operations)
+        .parse_next(input)
+}
+
+// Negative literals like `-1` are parsed as literals rather than as `neg 1`
+// since `method_call` and `expression_but_not_method_call` are tried first.
+fn unary(input: Input) -> IResult<Expression> {
+    let operator = alt(('-'.value("neg"), '!'.value("not")));
+    alt((
+        method_call,
+        expression_but_not_method_call,
+        (line, terminated(operator, ws), unary).map(|(line, name, operand)| {
+            Expression::MethodCall {
+                name: name.to_owned(),
+                this: Box::new(operand),
+                arguments: Vec::new(),
+                line,
+            }
+        }),
+    ))
+    .parse_next(input)
+}
+
+fn fold_binary_operations(
+    (first, rest): (Expression, Vec<(Operator, Expression)>),
+) -> Expression {
+    rest.into_iter().fold(first, |lhs, (operator, rhs)| {
+        binary_operation(operator, lhs, rhs)
+    })
+}
+
+/// The line of an operator along with the name of the method it stands for.
+type Operator<'a> = (usize, &'a str);
+
+/// `line` is where the operator is rather than where `lhs` starts.
+fn binary_operation(
+    (line, name): Operator,
+    lhs: Expression,
+    rhs: Expression,
+) -> Expression {
+    Expression::MethodCall {
+        name: name.to_owned(),
+        this: Box::new(lhs),
+        arguments: vec![rhs],
+        line,
+    }
 }
 
 // Without this, method calls would become right-associative, e.g. `f x y` would
@@ -76,80 +491,296 @@ fn expression_but_not_method_call(input: Input) -> IResult<Expression> {
         name_or_de_bruijn_index: ident,
     });
 
-    alt((
+    let primary = alt((
         unit_literal,
         parenthesized_expression,
         r#true,
         r#false,
-        block,
-        string_literal.map(Value::String).map(Expression::Literal),
+        keyword("break").value(Expression::Break),
+        keyword("continue").value(Expression::Continue),
+        // Bare blocks are only allowed as the bodies of `if` and `while`.
+        preceded((keyword("do"), ws), block),
+        string_literal
+            .map(|s| Value::String(s.into()))
+            .map(Expression::Literal),
+        f64_literal.map(Value::F64).map(Expression::Literal),
+        i64_literal.map(Value::I64).map(Expression::Literal),
         i32_literal.map(Value::I32).map(Expression::Literal),
         let_in,
+        closure,
+        preceded((keyword("new"), ws), identifier).map(Expression::New),
         if_then_else,
+        while_loop,
+        match_expression,
+        list_comprehension,
         local_variable,
-    ))
-    .parse_next(input)
+    ));
+
+    // Like arguments, `?.` binds tighter than method calls, so `f a?.b` passes
+    // `a?.b` to `f`.
+    (primary, repeat0(preceded("?.", identifier)))
+        .map(|(object, fields): (_, Vec<_>)| {
+            fields.into_iter().fold(object, |object, field| {
+                Expression::OptionalFieldGet {
+                    object: Box::new(object),
+                    field,
+                }
+            })
+        })
+        .parse_next(input)
 }
 
 fn parenthesized_expression(input: Input) -> IResult<Expression> {
     delimited(('(', ws), expression, (ws, ')')).parse_next(input)
 }
 
+// A trailing `;` discards the value of the last step like in Rust, so `{ a; }`
+// evaluates `a` but results in `()`, just like the empty block `{}`.
 fn block(input: Input) -> IResult<Expression> {
-    delimited('{', separated0(preceded(ws, expression), ';'), (ws, '}'))
-        .map(Expression::Do)
+    let steps = separated1(expression, (ws, ';', ws));
+    let has_trailing_semicolon =
+        opt((ws, ';')).map(|semicolon| semicolon.is_some());
+    delimited(('{', ws), opt((steps, has_trailing_semicolon)), (ws, '}'))
+        .map(|steps: Option<(Vec<_>, bool)>| match steps {
+            Some((mut steps, true)) => {
+                steps.push(Expression::Literal(Value::Unit));
+                Expression::Do(steps)
+            }
+            Some((steps, false)) => Expression::Do(steps),
+            None => Expression::Do(Vec::new()),
+        })
         .parse_next(input)
 }
 
+// A sign only belongs to the literal when it is immediately followed by a
+// digit. This is what makes `add x -1` pass `-1` as an argument, while `- 1`
+// (with a space) is not a literal at all and is left for operators.
 fn i32_literal(input: Input) -> IResult<i32> {
-    (
-        opt(one_of("+-")),
-        repeat1::<_, _, (), _, _>((digit1, take_while0('_'))),
-    )
+    terminated((opt(one_of("+-")), digits), end_of_number)
         .recognize()
-        .try_map(|s: Input| s.replace('_', "").parse())
+        .try_map(|s: &str| s.replace('_', "").parse())
         .parse_next(input)
 }
 
+// `I64` literals are told apart by their suffix, as in `5_000_000_000i64`.
+fn i64_literal(input: Input) -> IResult<i64> {
+    terminated(
+        (opt(one_of("+-")), digits).recognize(),
+        ("i64", end_of_number),
+    )
+    .try_map(|s: &str| s.replace('_', "").parse())
+    .parse_next(input)
+}
+
+fn f64_literal(input: Input) -> IResult<f64> {
+    terminated(
+        (
+            opt(one_of("+-")),
+            digits,
+            '.',
+            digits,
+            opt((one_of("eE"), opt(one_of("+-")), digit1)),
+        ),
+        end_of_number,
+    )
+    .recognize()
+    .try_map(|s: &str| s.replace('_', "").parse())
+    .parse_next(input)
+}
+
+// A single `_` can go between two digits to group them, so `1_000` is allowed
+// while `1_`, `1__0` and `1_.5` are not.
+fn digits(input: Input) -> IResult<()> {
+    separated1(digit1, '_').parse_next(input)
+}
+
+// Keeps `1_` from being read as `1` followed by the variable `_`, and `2x` as
+// `2` followed by `x`.
+fn end_of_number(input: Input) -> IResult<()> {
+    not(one_of(|c: char| c.is_alphanumeric() || c == '_')).parse_next(input)
+}
+
+// `let` bindings are immutable while `var` bindings can be reassigned.
+// `let x = a, y = b in body` is sugar for `let x = a in let y = b in body`.
 fn let_in(input: Input) -> IResult<Expression> {
-    (
-        preceded((keyword("let"), ws), identifier),
+    let binding = (
+        identifier,
         preceded((ws, '=', ws), expression.map(Box::new)),
-        preceded((ws, keyword("in"), ws), expression.map(Box::new)),
+    );
+    (
+        alt((
+            (keyword("let"), ws, keyword("rec")).value((false, true)),
+            keyword("let").value((false, false)),
+            keyword("var").value((true, false)),
+        )),
+        preceded(ws, separated1(binding, (ws, ',', ws))),
+        preceded((ws, keyword("in"), ws), expression),
     )
-        .map(|(name, bound, body)| Expression::LetIn { name, bound, body })
+        .map(
+            |((is_mutable, is_recursive), bindings, body): (_, Vec<_>, _)| {
+                bindings
+                    .into_iter()
+                    .rev()
+                    .fold(body, |body, (name, bound)| Expression::LetIn {
+                        name,
+                        is_mutable,
+                        is_recursive,
+                        bound,
+                        body: Box::new(body),
+                    })
+            },
+        )
         .parse_next(input)
 }
 
+// Like that of a `let`, the body extends as far to the right as possible.
+fn closure(input: Input) -> IResult<Expression> {
+    preceded(
+        (keyword("fn"), ws),
+        (
+            repeat0(terminated(identifier, ws)),
+            preceded(("->", ws), expression),
+        ),
+    )
+    .map(|(parameters, body)| Expression::Closure {
+        parameters,
+        body: Rc::new(body),
+    })
+    .parse_next(input)
+}
+
+fn list_comprehension(input: Input) -> IResult<Expression> {
+    delimited(
+        ('[', ws),
+        (
+            expression.map(Box::new),
+            preceded((ws, keyword("for"), ws), identifier),
+            preceded((ws, keyword("in"), ws), expression.map(Box::new)),
+            opt(preceded((ws, keyword("if"), ws), expression.map(Box::new))),
+        ),
+        (ws, ']'),
+    )
+    .map(
+        |(element, name, iterable, condition)| Expression::ListComprehension {
+            element,
+            name,
+            iterable,
+            condition,
+        },
+    )
+    .parse_next(input)
+}
+
+fn assignment(input: Input) -> IResult<Expression> {
+    (
+        identifier,
+        preceded((ws, '=', not('='), ws), expression.map(Box::new)),
+    )
+        .map(|(name, value)| Expression::Assign {
+            name_or_de_bruijn_index: name,
+            value,
+        })
+        .parse_next(input)
+}
+
+// An `if` without an `else` evaluates to `()` when the condition is false.
+// `else if` chains nest, so `if (a) {..} else if (b) {..} else {..}` is the
+// same as `if (a) {..} else { if (b) {..} else {..} }`.
 fn if_then_else(input: Input) -> IResult<Expression> {
     (
         preceded((keyword("if"), ws), parenthesized_expression.map(Box::new)),
         preceded(ws, block.map(Box::new)),
-        preceded((ws, keyword("else"), ws), block.map(Box::new)),
+        opt(preceded(
+            (ws, keyword("else"), ws),
+            alt((if_then_else, block)),
+        )),
     )
         .map(|(condition, if_true, if_false)| Expression::IfThenElse {
             condition,
             if_true,
-            if_false,
+            if_false: Box::new(
+                if_false.unwrap_or(Expression::Literal(Value::Unit)),
+            ),
         })
         .parse_next(input)
 }
 
+fn while_loop(input: Input) -> IResult<Expression> {
+    (
+        preceded(
+            (keyword("while"), ws),
+            parenthesized_expression.map(Box::new),
+        ),
+        preceded(ws, block.map(Box::new)),
+    )
+        .map(|(condition, body)| Expression::While { condition, body })
+        .parse_next(input)
+}
+
+// Arms are separated by `;`, and a `_` arm, if any, has to be the last one.
+fn match_expression(input: Input) -> IResult<Expression> {
+    let arm = (literal, preceded((ws, "=>", ws), expression));
+    let default = || preceded((keyword("_"), ws, "=>", ws), expression);
+    let arms = separated1(arm, (ws, ';', ws));
+    let body = alt((
+        (arms, opt(preceded((ws, ';', ws), default()))),
+        default().map(|default| (Vec::new(), Some(default))),
+    ));
+    (
+        preceded((keyword("match"), ws), expression.map(Box::new)),
+        delimited((ws, '{', ws), body, (ws, opt(';'), ws, '}')),
+    )
+        .map(|(subject, (arms, default))| Expression::Match {
+            subject,
+            arms,
+            default: default.map(Box::new),
+        })
+        .parse_next(input)
+}
+
+fn literal(input: Input) -> IResult<Value> {
+    alt((
+        ('(', ws, ')').value(Value::Unit),
+        keyword("true").value(Value::Bool(true)),
+        keyword("false").value(Value::Bool(false)),
+        string_literal.map(|s| Value::String(s.into())),
+        f64_literal.map(Value::F64),
+        i64_literal.map(Value::I64),
+        i32_literal.map(Value::I32),
+    ))
+    .parse_next(input)
+}
+
+// Arguments are separated by whitespace, so a signed literal argument must
+// keep its sign attached: `f x -1` is `f` applied to `x` and `-1`. A sign with
+// no whitespace in front of it is an operator instead, which makes `x-1` the
+// same as `x - 1` rather than `x` applied to `-1`.
 fn method_call(input: Input) -> IResult<Expression> {
+    let argument = || {
+        alt((
+            preceded(ws1, expression_but_not_method_call),
+            preceded(not(one_of("+-")), expression_but_not_method_call),
+        ))
+    };
+    // Otherwise `r"..."` would be a call of the method `r` on a string.
+    let raw_string_start = ('r', take_while(0.., '#'), '"');
     (
+        not(raw_string_start),
+        line,
         identifier,
-        preceded(ws, expression_but_not_method_call.map(Box::new)),
-        repeat0(preceded(ws, expression_but_not_method_call)),
+        argument().map(Box::new),
+        repeat0(argument()),
     )
-        .map(|(name, this, arguments)| Expression::MethodCall {
+        .map(|((), line, name, this, arguments)| Expression::MethodCall {
             name,
             this,
             arguments,
+            line,
         })
         .parse_next(input)
 }
 
-fn identifier_or_keyword(input: Input) -> IResult<&str> {
+fn identifier_or_keyword(input: Input<'_>) -> IResult<'_, &str> {
     (
         alt((alpha1, "_")),
         repeat0::<_, _, (), _, _>(alt((alphanumeric1, "_"))),
@@ -176,7 +807,28 @@ fn keyword<'a>(
 fn is_keyword(ident: &str) -> bool {
     matches!(
         ident,
-        "class" | "def" | "true" | "false" | "if" | "else" | "let" | "in"
+        "class"
+            | "def"
+            | "true"
+            | "false"
+            | "if"
+            | "else"
+            | "let"
+            | "var"
+            | "rec"
+            | "in"
+            | "for"
+            | "import"
+            | "while"
+            | "return"
+            | "break"
+            | "continue"
+            | "do"
+            | "match"
+            | "extends"
+            | "fn"
+            | "field"
+            | "new"
     )
 }
 
@@ -185,7 +837,49 @@ fn hex_digit(input: Input) -> IResult<char> {
 }
 
 fn string_literal(input: Input) -> IResult<String> {
+    alt((
+        raw_string_literal,
+        triple_quoted_string_literal,
+        escaped_string_literal,
+    ))
+    .parse_next(input)
+}
+
+// `r"..."` has no escape sequences, and `r#"..."#` can also contain `"` as
+// long as it isn't followed by as many `#`s as the literal started with.
+fn raw_string_literal(input: Input) -> IResult<String> {
+    let (input, hashes) =
+        delimited('r', take_while(0.., '#'), '"').parse_next(input)?;
+    let closing = format!("\"{hashes}");
+    let mut body =
+        cut_err(terminated(take_until0(closing.as_str()), closing.as_str()))
+            .map(ToOwned::to_owned);
+    body.parse_next(input)
+}
+
+fn escaped_string_literal(input: Input) -> IResult<String> {
     let normal = take_till1("\"\\\n").map(Cow::Borrowed);
+    delimited('"', repeat0(alt((normal, escape_sequence))), '"')
+        .map(|strs: Vec<_>| strs.concat())
+        .parse_next(input)
+}
+
+// `"""..."""` can span multiple lines and contain `"`s, and only `"""` ends it.
+// Everything in between is kept as is, including newlines and indentation,
+// except for escape sequences.
+fn triple_quoted_string_literal(input: Input) -> IResult<String> {
+    let normal = take_till1("\"\\").map(Cow::Borrowed);
+    let quote = terminated('"', not("\"\"")).value(Cow::Borrowed("\""));
+    delimited(
+        "\"\"\"",
+        repeat0(alt((normal, quote, escape_sequence))),
+        "\"\"\"",
+    )
+    .map(|strs: Vec<_>| strs.concat())
+    .parse_next(input)
+}
+
+fn escape_sequence(input: Input<'_>) -> IResult<'_, Cow<'_, str>> {
     let null = terminated('0', not(digit1)).value(Cow::Borrowed("\0"));
     let character_escape_sequence = alt((
         '"'.value("\""),
@@ -200,6 +894,15 @@ fn string_literal(input: Input) -> IResult<String> {
     ))
     .map(Cow::Borrowed);
 
+    alt((
+        preceded('\\', alt((character_escape_sequence, null))),
+        char_escape.map(String::from).map(Cow::Owned),
+    ))
+    .parse_next(input)
+}
+
+/// `\xHH`, `\uHHHH` or `\u{H...}`, which is not necessarily a valid `char`.
+fn code_point_escape(input: Input) -> IResult<u32> {
     let hex_escape_sequence =
         preceded('x', count::<_, _, (), _, _>(hex_digit, 2).recognize());
     let hex4digits = count::<_, _, (), _, _>(hex_digit, 4).recognize();
@@ -207,25 +910,22 @@ fn string_literal(input: Input) -> IResult<String> {
         delimited('{', take_while(1..=6, |c: char| c.is_ascii_hexdigit()), '}');
     let unicode_escape_sequence =
         preceded('u', alt((hex4digits, bracketed_unicode)));
-    let escape_sequence = preceded(
-        '\\',
-        alt((
-            character_escape_sequence,
-            null,
-            alt((hex_escape_sequence, unicode_escape_sequence))
-                .try_map(|digits| u32::from_str_radix(digits, 16))
-                .verify_map(|c| {
-                    char::from_u32(c).map(String::from).map(Cow::Owned)
-                }),
-        )),
-    );
-    let string_char = alt((normal, escape_sequence));
-
-    delimited('"', repeat0(string_char), '"')
-        .map(|strs: Vec<_>| strs.concat())
+    preceded('\\', alt((hex_escape_sequence, unicode_escape_sequence)))
+        .try_map(|digits| u32::from_str_radix(digits, 16))
         .parse_next(input)
 }
 
+// A surrogate or out of range code point can't be anything else, so it is
+// reported right where it is (see `explain`) instead of making the whole
+// string literal fail to parse.
+fn char_escape(input: Input) -> IResult<char> {
+    let (rest, code_point) = code_point_escape(input)?;
+    char::from_u32(code_point).map_or_else(
+        || Err(ErrMode::Cut(Error::new(input, ErrorKind::Verify))),
+        |c| Ok((rest, c)),
+    )
+}
+
 fn eol_comment(input: Input) -> IResult<()> {
     ("//", take_till0('\n').void()).void().parse_next(input)
 }
@@ -233,3 +933,101 @@ fn eol_comment(input: Input) -> IResult<()> {
 fn ws(input: Input) -> IResult<()> {
     repeat0(alt((multispace1.void(), eol_comment))).parse_next(input)
 }
+
+fn ws1(input: Input) -> IResult<()> {
+    repeat1(alt((multispace1.void(), eol_comment))).parse_next(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_semicolon_ends_a_method_definition() {
+        assert!(program("class Main { def f = do { 1; 2 }; }").is_ok());
+        let err = program("class Main { def f = 1; 2; }")
+            .expect_err("`2;` is not a class member");
+        assert_eq!((err.line, err.column), (1, 25));
+    }
+
+    #[test]
+    fn else_if_chains_nest_in_the_else_branch() {
+        let expression =
+            single_expression("if (a) { 1 } else if (b) { 2 } else { 3 }")
+                .expect("expression parses");
+        let Expression::IfThenElse { if_false, .. } = expression else {
+            panic!("expected an `if`, got {expression:?}");
+        };
+        let Expression::IfThenElse {
+            condition,
+            if_true,
+            if_false,
+        } = *if_false
+        else {
+            panic!("expected a nested `if`, got {if_false:?}");
+        };
+        assert!(matches!(
+            *condition,
+            Expression::LocalVariable { name_or_de_bruijn_index } if name_or_de_bruijn_index == "b"
+        ));
+        for (block, expected) in [(if_true, 2), (if_false, 3)] {
+            assert!(
+                matches!(
+                    &*block,
+                    Expression::Do(steps)
+                        if matches!(
+                            steps[..],
+                            [Expression::Literal(Value::I32(n))] if n == expected
+                        )
+                ),
+                "expected the block `{{ {expected} }}`, got {block:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn syntax_errors_show_the_offending_line() {
+        let err = program("class Main {\n  def main = (1 +;\n}")
+            .expect_err("`+;` is not an expression");
+        assert_eq!(err.line_text, "  def main = (1 +;");
+        assert_eq!(
+            err.to_string(),
+            "unexpected input at line 2, column 14:
+  |
+2 |   def main = (1 +;
+  |              ^"
+        );
+    }
+
+    #[test]
+    fn tokens_have_kinds_and_spans() {
+        let tokens = tokens(
+            r#"def f = add x -1 + x-1; // comment
+"s""#,
+        )
+        .expect("source should tokenize");
+        let tokens = tokens
+            .iter()
+            .map(|token| {
+                format!("{:?} {} {:?}", token.kind, token.text, token.span)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            [
+                "Keyword def 0..3",
+                "Identifier f 4..5",
+                "Punctuation = 6..7",
+                "Identifier add 8..11",
+                "Identifier x 12..13",
+                "Number -1 14..16",
+                "Punctuation + 17..18",
+                "Identifier x 19..20",
+                "Punctuation - 20..21",
+                "Number 1 21..22",
+                "Punctuation ; 22..23",
+                r#"String "s" 35..38"#,
+            ]
+        );
+    }
+}
@@ -1,17 +1,37 @@
-#[derive(Debug)]
-pub struct Program {
-    pub classes: Vec<Class>,
+use serde::{Deserialize, Serialize};
+
+type Unresolved = crate::expression::Of<String, String>;
+
+/// A parsed program, or a resolved one when `Body` is
+/// `crate::expression::Expression`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Program<Body = Unresolved> {
+    /// Paths relative to the importing file. `crate::load::program` merges
+    /// imported files into a single program, so this is empty afterwards.
+    pub imports: Vec<String>,
+    pub classes: Vec<Class<Body>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Class<Body = Unresolved> {
+    pub name: String,
+    /// The class named after `extends`, whose methods this class inherits.
+    pub superclass: Option<String>,
+    pub fields: Vec<Field<Body>>,
+    pub methods: Vec<ClassMethod<Body>>,
 }
 
-#[derive(Debug)]
-pub struct Class {
+/// `field name = initializer;`, where the initializer is evaluated whenever an
+/// object of the class is created.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Field<Body = Unresolved> {
     pub name: String,
-    pub methods: Vec<ClassMethod>,
+    pub initializer: Body,
 }
 
-#[derive(Debug)]
-pub struct ClassMethod {
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClassMethod<Body = Unresolved> {
     pub name: String,
     pub parameters: Vec<String>,
-    pub body: crate::expression::Of<String, String>,
+    pub body: Body,
 }
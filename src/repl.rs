@@ -0,0 +1,197 @@
+use crate::{
+    parse::{self, Line},
+    program::{Class, Program},
+    resolve::{self, LocalVariable, Resolver},
+    value::Value,
+    vm::VM,
+};
+use anyhow::{ensure, Context, Result};
+use std::io::{self, Write};
+
+/// Top-level bindings live at the bottom of the VM's local variable stack, in
+/// the same order as in the resolver, so that their De Bruijn indices line up
+/// just like they would inside a method body.
+struct Repl {
+    vm: VM,
+    resolver: Resolver,
+    class_names: Vec<String>,
+}
+
+pub fn run() -> Result<()> {
+    let mut repl = Repl::new();
+    let mut line = String::new();
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+        line.clear();
+        if io::stdin().read_line(&mut line)? == 0 {
+            // Ctrl-D; end the prompt line so the shell's prompt isn't glued
+            // onto it.
+            println!();
+            return Ok(());
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        match repl.evaluate_line(&line) {
+            Ok(Some(value)) => {
+                println!("{}", value.with_class_names(repl.vm.class_names()));
+            }
+            Ok(None) => {}
+            Err(err) => eprintln!("Error: {err:?}"),
+        }
+    }
+}
+
+impl Repl {
+    fn new() -> Self {
+        Self {
+            vm: VM::new(),
+            resolver: Resolver::default(),
+            class_names: Vec::new(),
+        }
+    }
+
+    fn evaluate_line(&mut self, line: &str) -> Result<Option<Value>> {
+        match parse::repl_line(line).context("syntax error")? {
+            Line::Class(class) => {
+                self.define_class(class)?;
+                Ok(None)
+            }
+            Line::Binding {
+                name,
+                is_mutable,
+                bound,
+            } => {
+                let bound = self.resolver.resolve_expression(bound)?;
+                let value = self.vm.evaluate(&bound)?;
+                self.resolver
+                    .local_variables
+                    .push(LocalVariable { name, is_mutable });
+                self.vm.push_local_variable(value);
+                Ok(None)
+            }
+            Line::Expression(expression) => {
+                let expression =
+                    self.resolver.resolve_expression(expression)?;
+                self.vm.evaluate(&expression).map(Some)
+            }
+        }
+    }
+
+    /// Since there is no other way to get hold of an object, each class is
+    /// bound to a variable of the same name holding an instance of it, just
+    /// like the one `main` is called on.
+    fn define_class(&mut self, class: Class) -> Result<()> {
+        ensure!(
+            !self.class_names.contains(&class.name),
+            "class `{}` is defined more than once",
+            class.name
+        );
+        // Only classes from earlier lines can be extended, which also rules
+        // out inheritance cycles.
+        if let Some(superclass) = &class.superclass {
+            ensure!(
+                self.class_names.contains(superclass),
+                "class `{}` extends undefined class `{superclass}`",
+                class.name
+            );
+        }
+        let name = class.name.clone();
+        let program = Program {
+            imports: Vec::new(),
+            classes: vec![resolve::class(
+                class,
+                &mut resolve::Warnings::default(),
+            )?],
+        };
+        let class_ids = self.vm.load_program(program)?;
+        let class = *class_ids
+            .get(&name)
+            .context("loaded class is missing from the VM")?;
+        // A field initializer can fail, so nothing is bound until the object
+        // exists. Otherwise the resolver would have a variable that the VM
+        // doesn't.
+        let object = self.vm.instantiate(class)?;
+        self.resolver.local_variables.push(LocalVariable {
+            name: name.clone(),
+            is_mutable: false,
+        });
+        self.vm.push_local_variable(object);
+        self.class_names.push(name);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Evaluates a line that is expected to succeed.
+    fn evaluate(repl: &mut Repl, line: &str) -> Option<Value> {
+        repl.evaluate_line(line)
+            .unwrap_or_else(|err| panic!("`{line}` failed: {err:?}"))
+    }
+
+    #[test]
+    fn error_in_let_body_leaves_bindings_intact() {
+        let mut repl = Repl::new();
+        evaluate(&mut repl, "let a = 1");
+        let err = repl
+            .evaluate_line(r#"let b = 2 in let c = 3 in error "boom""#)
+            .expect_err("error aborts the line");
+        assert_eq!(err.root_cause().to_string(), "boom");
+        evaluate(&mut repl, "let d = 4");
+        assert!(matches!(evaluate(&mut repl, "a"), Some(Value::I32(1))));
+        assert!(matches!(evaluate(&mut repl, "d"), Some(Value::I32(4))));
+        assert!(matches!(evaluate(&mut repl, "a + d"), Some(Value::I32(5))));
+    }
+
+    #[test]
+    fn bindings_persist_across_lines() {
+        let mut repl = Repl::new();
+        assert!(evaluate(&mut repl, "let x = 1").is_none());
+        assert!(evaluate(&mut repl, "var y = x + 1").is_none());
+        evaluate(&mut repl, "y = y * 10");
+        assert!(matches!(evaluate(&mut repl, "x + y"), Some(Value::I32(21))));
+    }
+
+    #[test]
+    fn classes_persist_across_lines() {
+        let mut repl = Repl::new();
+        evaluate(
+            &mut repl,
+            "class Greeter { def greet name = concat \"hi \" name; }",
+        );
+        let greeting = evaluate(&mut repl, r#"greet Greeter "you""#);
+        assert!(matches!(greeting, Some(Value::String(s)) if &*s == "hi you"));
+        assert!(repl
+            .evaluate_line("class Greeter {}")
+            .is_err_and(|err| err.to_string().contains("more than once")));
+    }
+
+    #[test]
+    fn failing_field_initializer_leaves_bindings_intact() {
+        let mut repl = Repl::new();
+        evaluate(&mut repl, "let a = 1");
+        evaluate(&mut repl, "let b = 2");
+        let err = repl
+            .evaluate_line("class Broken { field x = 1 / 0; }")
+            .expect_err("the initializer fails");
+        assert_eq!(err.root_cause().to_string(), "division by zero");
+        assert!(matches!(evaluate(&mut repl, "a"), Some(Value::I32(1))));
+        assert!(matches!(evaluate(&mut repl, "b"), Some(Value::I32(2))));
+        assert!(repl.evaluate_line("Broken").is_err());
+        evaluate(&mut repl, "class Broken { field x = 1; }");
+        let x = evaluate(&mut repl, r#"get Broken "x""#);
+        assert!(matches!(x, Some(Value::I32(1))));
+    }
+
+    #[test]
+    fn let_in_is_an_expression_in_the_repl() {
+        let mut repl = Repl::new();
+        let value = evaluate(&mut repl, "let x = 2 in x * x");
+        assert!(matches!(value, Some(Value::I32(4))));
+        assert!(repl.evaluate_line("x").is_err());
+    }
+}
@@ -1,14 +1,285 @@
-use crate::expression::{self, Expression};
-use anyhow::{anyhow, Result};
+use crate::{
+    expression::{self, Expression},
+    program::{Class, ClassMethod, Field, Program},
+};
+use anyhow::{anyhow, ensure, Result};
+use std::{collections::HashSet, fmt, rc::Rc};
 
+/// Resolves local variables to De Bruijn indices and checks that the program
+/// is well-formed, without loading it into a VM. Resolution carries on past
+/// errors so that all of them are reported at once.
+///
+/// Warnings are added to `warnings.found` whether or not resolution succeeds.
+pub fn program(
+    program: Program,
+    warnings: &mut Warnings,
+) -> Result<Program<Expression>> {
+    let mut errors = Vec::new();
+    let mut class_names = HashSet::new();
+    for class in &program.classes {
+        if !class_names.insert(class.name.as_str()) {
+            errors.push(anyhow!(
+                "class `{}` is defined more than once",
+                class.name
+            ));
+        }
+    }
+    // Each cycle is only reported once, even though every class in it and
+    // every class extending one of those runs into it.
+    let mut classes_in_cycles = HashSet::new();
+    for class in &program.classes {
+        let Some(superclass) = &class.superclass else {
+            continue;
+        };
+        if !class_names.contains(superclass.as_str()) {
+            errors.push(anyhow!(
+                "class `{}` extends undefined class `{superclass}`",
+                class.name
+            ));
+        } else if let Some(cycle) =
+            inheritance_cycle(&class.name, &program.classes)
+        {
+            if classes_in_cycles.insert(cycle[0]) {
+                classes_in_cycles.extend(cycle.iter().copied());
+                let cycle = cycle
+                    .iter()
+                    .map(|class| format!("`{class}`"))
+                    .collect::<Vec<_>>()
+                    .join(" -> ");
+                errors.push(anyhow!("inheritance cycle: {cycle}"));
+            }
+        }
+    }
+    let mut classes = Vec::new();
+    for class in program.classes {
+        match self::class(class, warnings) {
+            Ok(class) => classes.push(class),
+            Err(err) => errors.push(err),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(Errors::combine(errors));
+    }
+    Ok(Program {
+        imports: program.imports,
+        classes,
+    })
+}
+
+/// Resolves a class on its own. Unlike `program`, this doesn't check that its
+/// superclass exists, since that may have been loaded separately.
+pub fn class(
+    class: Class,
+    warnings: &mut Warnings,
+) -> Result<Class<Expression>> {
+    let mut errors = Vec::new();
+    // Methods can be overloaded by the number of parameters they take.
+    let mut signatures = HashSet::new();
+    let mut methods = Vec::new();
+    for method in class.methods {
+        if !signatures.insert((method.name.clone(), method.parameters.len())) {
+            errors.push(anyhow!(
+                "class `{}` defines method `{}` with {} parameters more than \
+                 once",
+                class.name,
+                method.name,
+                method.parameters.len()
+            ));
+        }
+        match self::method(method, warnings) {
+            Ok(method) => methods.push(method),
+            Err(err) => errors.push(err),
+        }
+    }
+    let mut field_names = HashSet::new();
+    let mut fields = Vec::new();
+    for field in class.fields {
+        if !field_names.insert(field.name.clone()) {
+            errors.push(anyhow!(
+                "class `{}` has more than one field named `{}`",
+                class.name,
+                field.name
+            ));
+        }
+        // Initializers are evaluated before the object exists, so there is no
+        // `this` for them to refer to.
+        let mut resolver = Resolver {
+            warnings: std::mem::take(warnings),
+            ..Resolver::default()
+        };
+        let initializer = resolver.resolve_expression(field.initializer);
+        *warnings = resolver.warnings;
+        match initializer {
+            Ok(initializer) => fields.push(Field {
+                name: field.name,
+                initializer,
+            }),
+            Err(err) => errors.push(err),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(Errors::combine(errors));
+    }
+    Ok(Class {
+        name: class.name,
+        superclass: class.superclass,
+        fields,
+        methods,
+    })
+}
+
+/// Walks up from `name` through the superclasses that `classes` define,
+/// returning the cycle it runs into if any, with its first class repeated at
+/// the end.
+fn inheritance_cycle<'a>(
+    name: &'a str,
+    classes: &'a [Class],
+) -> Option<Vec<&'a str>> {
+    let mut chain = vec![name];
+    while let Some(superclass) = classes
+        .iter()
+        .find(|class| class.name == chain[chain.len() - 1])
+        .and_then(|class| class.superclass.as_deref())
+    {
+        let is_cycle = chain.contains(&superclass);
+        chain.push(superclass);
+        if is_cycle {
+            let start = chain.iter().position(|class| *class == superclass)?;
+            return Some(chain.split_off(start));
+        }
+    }
+    None
+}
+
+/// Several errors found in one go. A lone error is never wrapped in this, so
+/// that it reads the same as it would without error recovery.
+#[derive(Debug)]
+pub struct Errors(Vec<anyhow::Error>);
+
+impl Errors {
+    fn combine(errors: Vec<anyhow::Error>) -> anyhow::Error {
+        // Errors from nested resolvers are flattened into a single list.
+        let mut errors = errors
+            .into_iter()
+            .flat_map(|err| match err.downcast::<Self>() {
+                Ok(Self(errors)) => errors,
+                Err(err) => vec![err],
+            })
+            .collect::<Vec<_>>();
+        if errors.len() == 1 {
+            errors.swap_remove(0)
+        } else {
+            Self(errors).into()
+        }
+    }
+}
+
+impl fmt::Display for Errors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} errors:", self.0.len())?;
+        for err in &self.0 {
+            write!(f, "\n  {err:#}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Errors {}
+
+fn method(
+    method: ClassMethod,
+    warnings: &mut Warnings,
+) -> Result<ClassMethod<Expression>> {
+    let mut parameter_names = HashSet::new();
+    for parameter in &method.parameters {
+        ensure!(
+            parameter != "this",
+            "method `{}` cannot have a parameter named `this`",
+            method.name
+        );
+        ensure!(
+            parameter_names.insert(parameter),
+            "method `{}` has more than one parameter named `{parameter}`",
+            method.name
+        );
+    }
+
+    let mut resolver = Resolver {
+        local_variables: std::iter::once("this".to_owned())
+            .chain(method.parameters.iter().cloned())
+            .map(|name| LocalVariable {
+                name,
+                is_mutable: false,
+            })
+            .collect(),
+        loop_depth: 0,
+        can_return: true,
+        warnings: std::mem::take(warnings),
+        errors: Vec::new(),
+    };
+    let body = resolver.resolve_expression(method.body);
+    *warnings = resolver.warnings;
+    let body = body?;
+    Ok(ClassMethod {
+        name: method.name,
+        parameters: method.parameters,
+        body,
+    })
+}
+
+#[derive(Default)]
 pub struct Resolver {
-    pub local_variables: Vec<String>,
+    pub local_variables: Vec<LocalVariable>,
+    /// How many `while` loops the expression being resolved is inside of, so
+    /// that `break` and `continue` outside of loops can be rejected.
+    pub loop_depth: usize,
+    /// Whether the expression being resolved is inside of a method or a
+    /// closure, which is where a `return` has something to return from.
+    /// Field initializers and REPL input are not.
+    pub can_return: bool,
+    pub warnings: Warnings,
+    /// Errors that resolution has recovered from, reported once the whole
+    /// expression has been resolved.
+    errors: Vec<anyhow::Error>,
+}
+
+/// Opt-in checks for code that is valid but likely a mistake.
+#[derive(Default)]
+pub struct Warnings {
+    /// Warn when a `let` or `var` binds a name that is already in scope.
+    pub shadowing: bool,
+    /// The warnings found so far, left for the caller to print.
+    pub found: Vec<String>,
+}
+
+pub struct LocalVariable {
+    pub name: String,
+    pub is_mutable: bool,
 }
 
 impl Resolver {
+    /// Resolves `expression`, reporting every undefined variable in it rather
+    /// than just the first one.
     pub fn resolve_expression(
         &mut self,
         expression: expression::Of<String, String>,
+    ) -> Result<Expression> {
+        let resolved = self.resolve(expression);
+        let mut errors = std::mem::take(&mut self.errors);
+        match resolved {
+            Ok(resolved) if errors.is_empty() => Ok(resolved),
+            Ok(_) => Err(Errors::combine(errors)),
+            Err(err) => {
+                errors.push(err);
+                Err(Errors::combine(errors))
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn resolve(
+        &mut self,
+        expression: expression::Of<String, String>,
     ) -> Result<Expression> {
         Ok(match expression {
             expression::Of::Literal(value) => expression::Of::Literal(value),
@@ -16,51 +287,289 @@ impl Resolver {
                 name,
                 this,
                 arguments,
+                line,
             } => expression::Of::MethodCall {
                 name,
-                this: Box::new(self.resolve_expression(*this)?),
+                this: Box::new(self.resolve(*this)?),
                 arguments: arguments
                     .into_iter()
-                    .map(|argument| self.resolve_expression(argument))
+                    .map(|argument| self.resolve(argument))
                     .collect::<Result<_>>()?,
+                line,
             },
             expression::Of::LocalVariable {
                 name_or_de_bruijn_index: name,
             } => expression::Of::LocalVariable {
+                // The index is never used since the error makes resolution
+                // fail in the end.
                 name_or_de_bruijn_index: self
-                    .local_variables
-                    .iter()
-                    .rev()
-                    .position(|variable| *variable == name)
-                    .ok_or_else(|| {
-                        anyhow!("variable `{name}` is not defined")
-                    })?,
+                    .de_bruijn_index(&name)
+                    .unwrap_or_else(|err| {
+                        self.errors.push(err);
+                        0
+                    }),
             },
-            expression::Of::LetIn { name, bound, body } => {
-                self.local_variables.push(name);
-                let result = expression::Of::LetIn {
-                    name: (),
-                    bound: Box::new(self.resolve_expression(*bound)?),
-                    body: Box::new(self.resolve_expression(*body)?),
-                };
+            expression::Of::LetIn {
+                name,
+                is_mutable,
+                is_recursive,
+                bound,
+                body,
+            } => {
+                if is_recursive {
+                    ensure!(
+                        matches!(*bound, expression::Of::Closure { .. }),
+                        "`let rec` can only bind a closure"
+                    );
+                    self.local_variables.push(LocalVariable {
+                        name: name.clone(),
+                        is_mutable: false,
+                    });
+                }
+                let bound = self.resolve(*bound);
+                if is_recursive {
+                    self.local_variables.pop();
+                }
+                let bound = bound?;
+                if self.warnings.shadowing
+                    && self.de_bruijn_index(&name).is_ok()
+                {
+                    let keyword = match (is_mutable, is_recursive) {
+                        (true, _) => "var",
+                        (false, true) => "let rec",
+                        (false, false) => "let",
+                    };
+                    self.warnings.found.push(format!(
+                        "`{keyword}` binding of `{name}` shadows an existing \
+                         variable"
+                    ));
+                }
+                self.local_variables
+                    .push(LocalVariable { name, is_mutable });
+                let body = self.resolve(*body);
                 self.local_variables.pop();
-                result
+                expression::Of::LetIn {
+                    name: (),
+                    is_mutable,
+                    is_recursive,
+                    bound: Box::new(bound),
+                    body: Box::new(body?),
+                }
+            }
+            expression::Of::Assign {
+                name_or_de_bruijn_index: name,
+                value,
+            } => {
+                let index = self.de_bruijn_index(&name)?;
+                ensure!(
+                    self.local_variables
+                        [self.local_variables.len() - 1 - index]
+                        .is_mutable,
+                    "cannot assign to immutable `{name}`"
+                );
+                expression::Of::Assign {
+                    name_or_de_bruijn_index: index,
+                    value: Box::new(self.resolve(*value)?),
+                }
             }
             expression::Of::IfThenElse {
                 condition,
                 if_true,
                 if_false,
             } => expression::Of::IfThenElse {
-                condition: Box::new(self.resolve_expression(*condition)?),
-                if_true: Box::new(self.resolve_expression(*if_true)?),
-                if_false: Box::new(self.resolve_expression(*if_false)?),
+                condition: Box::new(self.resolve(*condition)?),
+                if_true: Box::new(self.resolve(*if_true)?),
+                if_false: Box::new(self.resolve(*if_false)?),
             },
+            expression::Of::While { condition, body } => {
+                let condition = self.resolve(*condition)?;
+                self.loop_depth += 1;
+                let body = self.resolve(*body);
+                self.loop_depth -= 1;
+                expression::Of::While {
+                    condition: Box::new(condition),
+                    body: Box::new(body?),
+                }
+            }
+            expression::Of::Break => {
+                ensure!(self.loop_depth != 0, "`break` outside of a loop");
+                expression::Of::Break
+            }
+            expression::Of::Continue => {
+                ensure!(self.loop_depth != 0, "`continue` outside of a loop");
+                expression::Of::Continue
+            }
+            expression::Of::Return(value) => {
+                ensure!(self.can_return, "`return` outside of a method");
+                expression::Of::Return(Box::new(self.resolve(*value)?))
+            }
             expression::Of::Do(steps) => expression::Of::Do(
                 steps
                     .into_iter()
-                    .map(|step| self.resolve_expression(step))
+                    .map(|step| self.resolve(step))
                     .collect::<Result<_>>()?,
             ),
+            expression::Of::Match {
+                subject,
+                arms,
+                default,
+            } => expression::Of::Match {
+                subject: Box::new(self.resolve(*subject)?),
+                arms: arms
+                    .into_iter()
+                    .map(|(pattern, body)| Ok((pattern, self.resolve(body)?)))
+                    .collect::<Result<_>>()?,
+                default: default
+                    .map(|default| self.resolve(*default))
+                    .transpose()?
+                    .map(Box::new),
+            },
+            expression::Of::New(class) => expression::Of::New(class),
+            expression::Of::Closure { parameters, body } => {
+                let mut parameter_names = HashSet::new();
+                for parameter in &parameters {
+                    ensure!(
+                        parameter_names.insert(parameter),
+                        "closure has more than one parameter named \
+                         `{parameter}`"
+                    );
+                }
+                let parameter_count = parameters.len();
+                let local_variable_count = self.local_variables.len();
+                self.local_variables.extend(parameters.into_iter().map(
+                    |name| LocalVariable {
+                        name,
+                        is_mutable: false,
+                    },
+                ));
+                // Loops outside of the closure can't be broken out of from
+                // inside of it.
+                let loop_depth = std::mem::take(&mut self.loop_depth);
+                let can_return = std::mem::replace(&mut self.can_return, true);
+                let body = self.resolve(Rc::unwrap_or_clone(body));
+                self.loop_depth = loop_depth;
+                self.can_return = can_return;
+                self.local_variables.truncate(local_variable_count);
+                expression::Of::Closure {
+                    parameters: vec![(); parameter_count],
+                    body: Rc::new(body?),
+                }
+            }
+            expression::Of::ListComprehension {
+                element,
+                name,
+                iterable,
+                condition,
+            } => {
+                let iterable = self.resolve(*iterable)?;
+                self.local_variables.push(LocalVariable {
+                    name,
+                    is_mutable: false,
+                });
+                let element_and_condition =
+                    self.resolve(*element).and_then(|element| {
+                        let condition = condition
+                            .map(|condition| self.resolve(*condition))
+                            .transpose()?;
+                        Ok((element, condition))
+                    });
+                self.local_variables.pop();
+                let (element, condition) = element_and_condition?;
+                expression::Of::ListComprehension {
+                    element: Box::new(element),
+                    name: (),
+                    iterable: Box::new(iterable),
+                    condition: condition.map(Box::new),
+                }
+            }
+            expression::Of::OptionalFieldGet { object, field } => {
+                expression::Of::OptionalFieldGet {
+                    object: Box::new(self.resolve(*object)?),
+                    field,
+                }
+            }
         })
     }
+
+    fn de_bruijn_index(&self, name: &str) -> Result<usize> {
+        self.local_variables
+            .iter()
+            .rev()
+            .position(|variable| variable.name == name)
+            .ok_or_else(|| anyhow!("variable `{name}` is not defined"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn resolve(source: &str, warnings: &mut Warnings) -> Result<()> {
+        super::program(parse::program(source)?, warnings).map(drop)
+    }
+
+    #[test]
+    fn shadowing_is_collected_as_a_warning() {
+        let mut warnings = Warnings {
+            shadowing: true,
+            ..Warnings::default()
+        };
+        let source = "class Main { def main = let x = 1 in let x = 2 in x; }";
+        assert!(resolve(source, &mut warnings).is_ok());
+        assert_eq!(
+            warnings.found,
+            ["`let` binding of `x` shadows an existing variable"]
+        );
+    }
+
+    #[test]
+    fn shadowing_warnings_name_the_kind_of_binding() {
+        let mut warnings = Warnings {
+            shadowing: true,
+            ..Warnings::default()
+        };
+        let source = "class Main {
+            def main x = var x = 1 in let rec x = fn -> x in x;
+        }";
+        assert!(resolve(source, &mut warnings).is_ok());
+        assert_eq!(
+            warnings.found,
+            [
+                "`var` binding of `x` shadows an existing variable",
+                "`let rec` binding of `x` shadows an existing variable",
+            ]
+        );
+    }
+
+    #[test]
+    fn shadowing_is_not_checked_by_default() {
+        let mut warnings = Warnings::default();
+        let source = "class Main { def main = let x = 1 in let x = 2 in x; }";
+        assert!(resolve(source, &mut warnings).is_ok());
+        assert!(warnings.found.is_empty());
+    }
+
+    #[test]
+    fn every_undefined_variable_is_reported() {
+        let source = "class Main { def main = add a b; def f = c; }";
+        let err = resolve(source, &mut Warnings::default())
+            .expect_err("no variables are defined");
+        let err = format!("{err:#}");
+        assert!(err.starts_with("3 errors:"), "{err}");
+        for name in ["a", "b", "c"] {
+            assert!(
+                err.contains(&format!("variable `{name}` is not defined")),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn a_single_error_is_not_wrapped() {
+        let source = "class Main { def main = a; }";
+        let err = resolve(source, &mut Warnings::default())
+            .expect_err("`a` is not defined");
+        assert!(!format!("{err:#}").contains("errors:"), "{err:#}");
+    }
 }
@@ -1,4 +1,4 @@
-use crate::vm::ClassID;
+use crate::vm::{ClassID, ClassNames};
 use std::fmt;
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
@@ -7,9 +7,44 @@ pub enum Type {
     Unit,
     Bool,
     I32,
+    I64,
+    F64,
     String,
+    List,
+    Map,
+    Closure,
 }
 
+impl Type {
+    /// Looks up a type other than a class by its name.
+    pub fn builtin(name: &str) -> Option<Self> {
+        Some(match name {
+            "Unit" => Self::Unit,
+            "Bool" => Self::Bool,
+            "I32" => Self::I32,
+            "I64" => Self::I64,
+            "F64" => Self::F64,
+            "String" => Self::String,
+            "List" => Self::List,
+            "Map" => Self::Map,
+            "Closure" => Self::Closure,
+            _ => return None,
+        })
+    }
+
+    pub const fn with_class_names(
+        self,
+        class_names: &ClassNames,
+    ) -> WithClassNames<'_> {
+        WithClassNames {
+            typ: self,
+            class_names,
+        }
+    }
+}
+
+/// Classes are shown as `Class_N` since a `ClassID` doesn't know its name;
+/// use `with_class_names` to show the name instead.
 impl fmt::Display for Type {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -17,7 +52,30 @@ impl fmt::Display for Type {
             Self::Unit => f.write_str("Unit"),
             Self::Bool => f.write_str("Bool"),
             Self::I32 => f.write_str("I32"),
+            Self::I64 => f.write_str("I64"),
+            Self::F64 => f.write_str("F64"),
             Self::String => f.write_str("String"),
+            Self::List => f.write_str("List"),
+            Self::Map => f.write_str("Map"),
+            Self::Closure => f.write_str("Closure"),
+        }
+    }
+}
+
+/// A type formatted with classes shown by their names.
+pub struct WithClassNames<'a> {
+    typ: Type,
+    class_names: &'a ClassNames,
+}
+
+impl fmt::Display for WithClassNames<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.typ {
+            Type::Object(class_id) => match self.class_names.get(&class_id) {
+                Some(name) => f.write_str(name),
+                None => self.typ.fmt(f),
+            },
+            _ => self.typ.fmt(f),
         }
     }
 }
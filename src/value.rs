@@ -1,13 +1,27 @@
-use crate::{object::Object, typ::Type};
-use std::rc::Rc;
+use crate::{
+    closure::Closure,
+    object::Object,
+    typ::Type,
+    vm::{ClassID, ClassNames},
+};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Value {
     Object(Rc<Object>),
     Unit,
     Bool(bool),
     I32(i32),
-    String(String),
+    I64(i64),
+    F64(f64),
+    /// Reference counted so that passing strings around doesn't copy them.
+    String(Rc<str>),
+    List(Rc<Vec<Self>>),
+    /// Unlike lists, maps are mutable and shared between all copies.
+    Map(Rc<RefCell<HashMap<String, Self>>>),
+    Closure(Rc<Closure>),
 }
 
 impl Value {
@@ -17,7 +31,422 @@ impl Value {
             Self::Unit => Type::Unit,
             Self::Bool(_) => Type::Bool,
             Self::I32(_) => Type::I32,
+            Self::I64(_) => Type::I64,
+            Self::F64(_) => Type::F64,
             Self::String(_) => Type::String,
+            Self::List(_) => Type::List,
+            Self::Map(_) => Type::Map,
+            Self::Closure(_) => Type::Closure,
         }
     }
+
+    pub fn as_object(&self) -> Result<&Rc<Object>> {
+        match self {
+            Self::Object(object) => Ok(object),
+            _ => bail!("expected an object, got `{}`", self.typ()),
+        }
+    }
+
+    pub fn as_bool(&self) -> Result<bool> {
+        match *self {
+            Self::Bool(b) => Ok(b),
+            _ => bail!("expected `Bool`, got `{}`", self.typ()),
+        }
+    }
+
+    pub fn as_i32(&self) -> Result<i32> {
+        match *self {
+            Self::I32(n) => Ok(n),
+            _ => bail!("expected `I32`, got `{}`", self.typ()),
+        }
+    }
+
+    pub fn as_i64(&self) -> Result<i64> {
+        match *self {
+            Self::I64(n) => Ok(n),
+            _ => bail!("expected `I64`, got `{}`", self.typ()),
+        }
+    }
+
+    pub fn as_f64(&self) -> Result<f64> {
+        match *self {
+            Self::F64(x) => Ok(x),
+            _ => bail!("expected `F64`, got `{}`", self.typ()),
+        }
+    }
+
+    pub fn as_str(&self) -> Result<&str> {
+        match self {
+            Self::String(s) => Ok(s),
+            _ => bail!("expected `String`, got `{}`", self.typ()),
+        }
+    }
+
+    pub fn as_list(&self) -> Result<&Rc<Vec<Self>>> {
+        match self {
+            Self::List(elements) => Ok(elements),
+            _ => bail!("expected `List`, got `{}`", self.typ()),
+        }
+    }
+
+    pub fn as_map(&self) -> Result<&Rc<RefCell<HashMap<String, Self>>>> {
+        match self {
+            Self::Map(entries) => Ok(entries),
+            _ => bail!("expected `Map`, got `{}`", self.typ()),
+        }
+    }
+
+    pub fn as_closure(&self) -> Result<&Rc<Closure>> {
+        match self {
+            Self::Closure(closure) => Ok(closure),
+            _ => bail!("expected `Closure`, got `{}`", self.typ()),
+        }
+    }
+}
+
+/// Objects, maps and closures are compared by identity rather than
+/// structurally, so comparing them never has to walk (possibly cyclic) graphs
+/// of references.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Object(a), Self::Object(b)) => Rc::ptr_eq(a, b),
+            (Self::Unit, Self::Unit) => true,
+            (Self::Bool(a), Self::Bool(b)) => a == b,
+            (Self::I32(a), Self::I32(b)) => a == b,
+            (Self::I64(a), Self::I64(b)) => a == b,
+            (Self::F64(a), Self::F64(b)) => a == b,
+            (Self::String(a), Self::String(b)) => a == b,
+            (Self::List(a), Self::List(b)) => a == b,
+            (Self::Map(a), Self::Map(b)) => Rc::ptr_eq(a, b),
+            (Self::Closure(a), Self::Closure(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// Objects are shown as `Class_N` since they don't know the names of their
+/// classes; use `with_class_names` to show those instead.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with(f, &ClassNames::new(), &mut Vec::new())
+    }
+}
+
+/// A value formatted with objects shown by the names of their classes.
+pub struct WithClassNames<'a> {
+    value: &'a Value,
+    class_names: &'a ClassNames,
+}
+
+impl fmt::Display for WithClassNames<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.value.fmt_with(f, self.class_names, &mut Vec::new())
+    }
+}
+
+/// A multi-line dump of a value and everything in it, like `{:#?}` in Rust.
+pub struct Dump<'a> {
+    value: &'a Value,
+    class_names: &'a ClassNames,
+}
+
+impl fmt::Display for Dump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.value.fmt_dump(f, self.class_names, 0, &mut Vec::new())
+    }
+}
+
+type MapPointer = *const RefCell<HashMap<String, Value>>;
+
+impl Value {
+    pub const fn with_class_names<'a>(
+        &'a self,
+        class_names: &'a ClassNames,
+    ) -> WithClassNames<'a> {
+        WithClassNames {
+            value: self,
+            class_names,
+        }
+    }
+
+    pub const fn dump<'a>(&'a self, class_names: &'a ClassNames) -> Dump<'a> {
+        Dump {
+            value: self,
+            class_names,
+        }
+    }
+
+    /// `maps` are the maps that are currently being formatted, like for
+    /// `fmt_dump`.
+    fn fmt_with(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        class_names: &ClassNames,
+        maps: &mut Vec<MapPointer>,
+    ) -> fmt::Result {
+        match self {
+            Self::Object(object) => {
+                fmt_class_name(f, object.class, class_names)?;
+                let mut names = object.properties.keys().collect::<Vec<_>>();
+                if names.is_empty() {
+                    return f.write_str(" {}");
+                }
+                names.sort_unstable();
+                f.write_str(" { ")?;
+                for (i, name) in names.into_iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{name}: ")?;
+                    object.properties[name].fmt_nested(f, class_names, maps)?;
+                }
+                f.write_str(" }")
+            }
+            Self::Unit => f.write_str("()"),
+            Self::Bool(b) => fmt::Display::fmt(b, f),
+            Self::I32(n) => fmt::Display::fmt(n, f),
+            Self::I64(n) => fmt::Display::fmt(n, f),
+            // Unlike `Display`, `Debug` always includes a decimal point.
+            Self::F64(x) => write!(f, "{x:?}"),
+            Self::String(s) => f.write_str(s),
+            Self::List(elements) => {
+                f.write_str("[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(", ")?;
+                    }
+                    element.fmt_nested(f, class_names, maps)?;
+                }
+                f.write_str("]")
+            }
+            Self::Map(entries) if maps.contains(&Rc::as_ptr(entries)) => {
+                f.write_str("{...}")
+            }
+            Self::Map(entries) => {
+                maps.push(Rc::as_ptr(entries));
+                let entries = entries.borrow();
+                let mut keys = entries.keys().collect::<Vec<_>>();
+                keys.sort_unstable();
+                f.write_str("{")?;
+                for (i, key) in keys.into_iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{key:?}: ")?;
+                    entries[key].fmt_nested(f, class_names, maps)?;
+                }
+                maps.pop();
+                f.write_str("}")
+            }
+            Self::Closure(_) => f.write_str("<closure>"),
+        }
+    }
+
+    /// `maps` are the maps that are currently being dumped. Maps are the only
+    /// values that can contain themselves, so this is what keeps cycles from
+    /// recursing forever; they are shown as `{...}` instead.
+    fn fmt_dump(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        class_names: &ClassNames,
+        indent: usize,
+        maps: &mut Vec<MapPointer>,
+    ) -> fmt::Result {
+        let newline = |f: &mut fmt::Formatter<'_>, indent: usize| {
+            write!(f, "\n{:1$}", "", indent * 4)
+        };
+        match self {
+            Self::Object(object) if !object.properties.is_empty() => {
+                fmt_class_name(f, object.class, class_names)?;
+                f.write_str(" {")?;
+                let mut names = object.properties.keys().collect::<Vec<_>>();
+                names.sort_unstable();
+                for name in names {
+                    newline(f, indent + 1)?;
+                    write!(f, "{name}: ")?;
+                    object.properties[name].fmt_dump(
+                        f,
+                        class_names,
+                        indent + 1,
+                        maps,
+                    )?;
+                    f.write_str(",")?;
+                }
+                newline(f, indent)?;
+                f.write_str("}")
+            }
+            Self::Object(_) => self.fmt_with(f, class_names, maps),
+            Self::List(elements) if !elements.is_empty() => {
+                f.write_str("[")?;
+                for element in elements.iter() {
+                    newline(f, indent + 1)?;
+                    element.fmt_dump(f, class_names, indent + 1, maps)?;
+                    f.write_str(",")?;
+                }
+                newline(f, indent)?;
+                f.write_str("]")
+            }
+            Self::Map(entries) if maps.contains(&Rc::as_ptr(entries)) => {
+                f.write_str("{...}")
+            }
+            Self::Map(entries) if !entries.borrow().is_empty() => {
+                maps.push(Rc::as_ptr(entries));
+                let entries = entries.borrow();
+                let mut keys = entries.keys().collect::<Vec<_>>();
+                keys.sort_unstable();
+                f.write_str("{")?;
+                for key in keys {
+                    newline(f, indent + 1)?;
+                    write!(f, "{key:?}: ")?;
+                    entries[key].fmt_dump(f, class_names, indent + 1, maps)?;
+                    f.write_str(",")?;
+                }
+                newline(f, indent)?;
+                maps.pop();
+                f.write_str("}")
+            }
+            _ => self.fmt_nested(f, class_names, maps),
+        }
+    }
+
+    /// Strings inside lists and maps are quoted so that `["a, b"]` and
+    /// `["a", "b"]` can be told apart.
+    fn fmt_nested(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        class_names: &ClassNames,
+        maps: &mut Vec<MapPointer>,
+    ) -> fmt::Result {
+        if let Self::String(s) = self {
+            write!(f, "{s:?}")
+        } else {
+            self.fmt_with(f, class_names, maps)
+        }
+    }
+}
+
+/// Classes that aren't in `class_names` fall back to `Class_N`.
+fn fmt_class_name(
+    f: &mut fmt::Formatter<'_>,
+    class: ClassID,
+    class_names: &ClassNames,
+) -> fmt::Result {
+    match class_names.get(&class) {
+        Some(name) => f.write_str(name),
+        None => write!(f, "Class_{class}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::VM;
+    use std::io;
+
+    /// One value of every kind, with a few that only differ in identity or
+    /// in their contents.
+    fn samples() -> Vec<Value> {
+        let class = VM::without_prelude(io::sink()).new_class_id();
+        let object = || {
+            Value::Object(Rc::new(Object {
+                class,
+                properties: HashMap::new(),
+            }))
+        };
+        let closure = || {
+            Value::Closure(Rc::new(Closure {
+                parameter_count: 0,
+                captured: Vec::new(),
+                recursive_slot: None,
+                body: Rc::new(crate::expression::Expression::Break),
+            }))
+        };
+        let list = |elements: &[i32]| {
+            Value::List(Rc::new(
+                elements.iter().copied().map(Value::I32).collect(),
+            ))
+        };
+        vec![
+            object(),
+            object(),
+            Value::Unit,
+            Value::Bool(false),
+            Value::Bool(true),
+            Value::I32(0),
+            Value::I32(1),
+            Value::I64(0),
+            Value::F64(0.0),
+            Value::F64(-0.0),
+            Value::F64(f64::NAN),
+            Value::String("".into()),
+            Value::String("0".into()),
+            list(&[]),
+            list(&[0]),
+            list(&[0, 1]),
+            Value::Map(Rc::default()),
+            Value::Map(Rc::default()),
+            closure(),
+            closure(),
+        ]
+    }
+
+    fn is_nan(value: &Value) -> bool {
+        matches!(value, Value::F64(x) if x.is_nan())
+    }
+
+    #[test]
+    fn equality_is_reflexive_except_for_nan() {
+        for value in samples() {
+            assert_eq!(value == value, !is_nan(&value), "{value:?}");
+        }
+    }
+
+    #[test]
+    fn equality_is_symmetric() {
+        let samples = samples();
+        for a in &samples {
+            for b in &samples {
+                assert_eq!(a == b, b == a, "{a:?} and {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn values_of_different_types_are_never_equal() {
+        let samples = samples();
+        for a in &samples {
+            for b in &samples {
+                if a.typ() != b.typ() {
+                    assert_ne!(a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn objects_maps_and_closures_are_compared_by_identity() {
+        let samples = samples();
+        for (i, a) in samples.iter().enumerate() {
+            for (j, b) in samples.iter().enumerate() {
+                if i != j
+                    && matches!(
+                        a,
+                        Value::Object(_) | Value::Map(_) | Value::Closure(_)
+                    )
+                {
+                    assert_ne!(a, b, "{a:?} and {b:?}");
+                }
+            }
+            assert_eq!(a.clone() == *a, !is_nan(a), "{a:?}");
+        }
+    }
+
+    #[test]
+    fn lists_and_strings_are_compared_structurally() {
+        let list = || Value::List(Rc::new(vec![Value::String("a".into())]));
+        assert_eq!(list(), list());
+        assert_eq!(Value::String("a".into()), Value::String("a".into()));
+        assert_eq!(Value::F64(0.0), Value::F64(-0.0));
+    }
 }
@@ -1,24 +1,90 @@
 use crate::{
+    closure::Closure,
     expression::Expression,
-    method::{default_methods, Method},
+    method::{
+        self, default_methods, object_methods, universal_methods, Method,
+    },
     object::Object,
-    program::Program,
-    resolve::Resolver,
+    parse,
+    program::{ClassMethod, Field, Program},
+    resolve,
     typ::Type,
     value::Value,
 };
-use anyhow::{Context, Result};
-use std::{collections::HashMap, fmt, rc::Rc};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    io::{self, Write},
+    rc::Rc,
+};
 
 pub struct VM {
     methods: HashMap<Type, HashMap<String, Rc<Method>>>,
+    /// Methods defined by the program, keyed by the type they belong to, their
+    /// name and how many parameters they take, since they can be overloaded
+    /// by the latter. These take precedence over builtin methods.
+    custom_methods: HashMap<(Type, String, usize), Rc<Method>>,
+    /// Consulted for objects of any class after their own methods.
+    object_methods: HashMap<String, Rc<Method>>,
+    universal_methods: HashMap<String, Rc<Method>>,
+    /// Memoizes `lookup_method`. This has to be cleared whenever
+    /// `custom_methods` changes.
+    method_cache: HashMap<(Type, String, usize), Rc<Method>>,
     local_variables: Vec<Value>,
+    /// Where the local variables of the innermost method or closure call
+    /// start. Those are exactly the ones a closure created there captures.
+    frame_start: usize,
     class_id_counter: usize,
+    /// Every class loaded so far, so that later programs can extend them.
+    class_ids: HashMap<String, ClassID>,
+    superclasses: HashMap<ClassID, ClassID>,
+    class_names: ClassNames,
+    /// The fields that each class declares itself, not counting inherited
+    /// ones.
+    fields: HashMap<ClassID, Rc<[Field<Expression>]>>,
+    program_arguments: Rc<Vec<Value>>,
+    output: Box<dyn Write>,
+    /// Where `eprintln` and `eprint` write to.
+    error_output: Box<dyn Write>,
+    /// Whether builtins that reach outside of the VM, like `read_file`, are
+    /// disabled.
+    is_sandboxed: bool,
+    method_fallback: Option<Rc<MethodFallback>>,
+    /// The value of the `return` that is currently unwinding, if any.
+    return_value: Value,
+    /// How many expressions may be evaluated in total, if limited.
+    step_limit: Option<u64>,
+    steps: u64,
+    /// How many method and closure calls may be nested, if limited.
+    call_depth_limit: Option<usize>,
+    call_depth: usize,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Methods written in ol that every VM has unless created with
+/// `VM::without_prelude`.
+const PRELUDE: &str = include_str!("prelude.ol");
+
+/// Called when a receiver has no method with the given name. Returning `None`
+/// falls through to the usual error.
+pub type MethodFallback =
+    dyn Fn(&mut VM, &Value, &str, &[Value]) -> Option<Result<Value>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ClassID(usize);
 
+/// Objects only know the `ClassID` of their class, so showing its name
+/// requires one of these.
+pub type ClassNames = HashMap<ClassID, String>;
+
 impl fmt::Display for ClassID {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.0.fmt(f)
@@ -27,61 +93,450 @@ impl fmt::Display for ClassID {
 
 impl VM {
     pub fn new() -> Self {
+        Self::with_output(io::stdout())
+    }
+
+    /// Creates a VM whose output builtins like `println` write to `output`
+    /// instead of standard output.
+    pub fn with_output(output: impl Write + 'static) -> Self {
+        let mut vm = Self::without_prelude(output);
+        vm.load_prelude();
+        vm
+    }
+
+    /// Creates a VM whose output builtins write nowhere and whose file system
+    /// builtins fail, so that running a program can't have any effects outside
+    /// of the VM.
+    ///
+    /// This alone doesn't make untrusted programs safe to run: use a step limit
+    /// to keep them from running forever and a call depth limit to keep deep
+    /// recursion from overflowing the native stack.
+    pub fn sandboxed() -> Self {
+        let mut vm = Self::with_output(io::sink());
+        vm.error_output = Box::new(io::sink());
+        vm.is_sandboxed = true;
+        vm
+    }
+
+    /// Creates a VM that only has the builtin methods written in Rust.
+    pub fn without_prelude(output: impl Write + 'static) -> Self {
         Self {
             methods: default_methods(),
+            custom_methods: HashMap::new(),
+            object_methods: object_methods(),
+            universal_methods: universal_methods(),
+            method_cache: HashMap::new(),
             local_variables: Vec::new(),
+            frame_start: 0,
             class_id_counter: 0,
+            class_ids: HashMap::new(),
+            superclasses: HashMap::new(),
+            class_names: ClassNames::new(),
+            fields: HashMap::new(),
+            program_arguments: Rc::default(),
+            output: Box::new(output),
+            error_output: Box::new(io::stderr()),
+            is_sandboxed: false,
+            method_fallback: None,
+            return_value: Value::Unit,
+            step_limit: None,
+            steps: 0,
+            call_depth_limit: None,
+            call_depth: 0,
         }
     }
 
+    pub fn set_program_arguments(&mut self, arguments: Vec<String>) {
+        self.program_arguments = Rc::new(
+            arguments
+                .into_iter()
+                .map(|arg| Value::String(arg.into()))
+                .collect(),
+        );
+    }
+
+    pub fn program_arguments(&self) -> Rc<Vec<Value>> {
+        self.program_arguments.clone()
+    }
+
+    pub fn output(&mut self) -> &mut dyn Write {
+        &mut self.output
+    }
+
+    pub fn error_output(&mut self) -> &mut dyn Write {
+        &mut self.error_output
+    }
+
+    pub const fn is_sandboxed(&self) -> bool {
+        self.is_sandboxed
+    }
+
+    /// Makes evaluation fail once `limit` expressions have been evaluated, so
+    /// that programs that loop forever without growing the stack still end.
+    pub const fn with_step_limit(mut self, limit: u64) -> Self {
+        self.step_limit = Some(limit);
+        self
+    }
+
+    /// Makes calls fail once `limit` method and closure calls are nested, so
+    /// that deep recursion is reported as an error instead of overflowing the
+    /// native stack. How deep is safe depends on the stack size of the thread
+    /// running the VM. Self-recursive calls in tail position don't nest.
+    pub const fn with_call_depth_limit(mut self, limit: usize) -> Self {
+        self.call_depth_limit = Some(limit);
+        self
+    }
+
+    pub fn set_method_fallback(&mut self, fallback: Box<MethodFallback>) {
+        self.method_fallback = Some(Rc::from(fallback));
+    }
+
+    /// Nothing is loaded if the program has an undefined superclass or an
+    /// inheritance cycle.
     pub fn load_program(
         &mut self,
-        program: Program,
+        program: Program<Expression>,
     ) -> Result<HashMap<String, ClassID>> {
-        let mut class_ids = HashMap::new();
-        for class in program.classes {
-            let class_id = self.new_class_id();
-            class_ids.insert(class.name, class_id);
-            for method in class.methods {
-                let mut resolver = Resolver {
-                    local_variables: std::iter::once("this".to_owned())
-                        .chain(method.parameters)
-                        .collect(),
+        let class_ids = program
+            .classes
+            .iter()
+            .map(|class| (class.name.clone(), self.new_class_id()))
+            .collect::<HashMap<_, _>>();
+        let mut superclasses = HashMap::new();
+        for class in &program.classes {
+            let Some(superclass) = &class.superclass else {
+                continue;
+            };
+            // `resolve::program` has checked the superclasses that are part of
+            // `program`, but classes loaded earlier can be extended too.
+            let superclass_id = class_ids
+                .get(superclass)
+                .or_else(|| self.class_ids.get(superclass))
+                .with_context(|| {
+                    format!(
+                        "class `{}` extends undefined class `{superclass}`",
+                        class.name
+                    )
+                })?;
+            superclasses.insert(class_ids[&class.name], *superclass_id);
+        }
+        // Classes loaded earlier can't extend the new ones, so any cycle is
+        // made up of classes from `program` alone.
+        for class in &program.classes {
+            if let Some(cycle) =
+                inheritance_cycle(class_ids[&class.name], &superclasses)
+            {
+                let class_name = |id| {
+                    class_ids
+                        .iter()
+                        .find(|(_, class_id)| **class_id == id)
+                        .map_or("", |(name, _)| name.as_str())
                 };
-                let body = resolver.resolve_expression(method.body)?;
-                self.methods
-                    .entry(Type::Object(class_id))
-                    .or_default()
-                    .insert(
-                        method.name.clone(),
-                        Rc::new(Method::Custom { body }),
-                    );
+                let cycle = cycle
+                    .into_iter()
+                    .map(|id| format!("`{}`", class_name(id)))
+                    .collect::<Vec<_>>()
+                    .join(" -> ");
+                bail!("inheritance cycle: {cycle}");
             }
         }
+
+        self.superclasses.extend(superclasses);
+        self.class_names
+            .extend(class_ids.iter().map(|(name, id)| (*id, name.clone())));
+        self.class_ids.extend(class_ids.clone());
+        for mut class in program.classes {
+            for field in &mut class.fields {
+                self.fold_constants(&mut field.initializer);
+            }
+            for method in &mut class.methods {
+                self.fold_constants(&mut method.body);
+            }
+            let class_id = class_ids[&class.name];
+            self.fields.insert(class_id, class.fields.into());
+            self.define_methods(Type::Object(class_id), class.methods);
+        }
         Ok(class_ids)
     }
 
-    pub fn run(&mut self, main_type: ClassID) -> Result<()> {
-        let main_method = self
-            .methods
-            .get(&Type::Object(main_type))
-            .and_then(|methods| methods.get("main"))
-            .context("program has no entry point")?
-            .clone();
-        let this = Value::Object(Rc::new(Object {
-            class: main_type,
-            properties: HashMap::default(),
-        }));
-        self.invoke_method(&main_method, this, Vec::new())?;
+    /// Replaces calls to pure builtins whose receiver and arguments are all
+    /// literals with their results. Programs can't add methods to builtin
+    /// types, so the builtin being called can't change later on. Calls that
+    /// fail are left alone so that the error happens when they are evaluated.
+    fn fold_constants(&mut self, expression: &mut Expression) {
+        match expression {
+            Expression::Literal(_)
+            | Expression::LocalVariable { .. }
+            | Expression::Break
+            | Expression::Continue
+            | Expression::New(_) => {}
+            Expression::MethodCall {
+                name,
+                this,
+                arguments,
+                line: _,
+            } => {
+                self.fold_constants(this);
+                for argument in &mut *arguments {
+                    self.fold_constants(argument);
+                }
+                let Expression::Literal(this) = &**this else {
+                    return;
+                };
+                if !method::is_pure(this.typ(), name) {
+                    return;
+                }
+                let Some(arguments) = arguments
+                    .iter()
+                    .map(|argument| match argument {
+                        Expression::Literal(value) => Some(value.clone()),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>()
+                else {
+                    return;
+                };
+                let Some(method) =
+                    self.lookup_method(this.typ(), name, arguments.len())
+                else {
+                    return;
+                };
+                let Method::Builtin(f) = *method else {
+                    return;
+                };
+                if let Ok(value) = f(self, this, &arguments) {
+                    *expression = Expression::Literal(value);
+                }
+            }
+            Expression::LetIn { bound, body, .. } => {
+                self.fold_constants(bound);
+                self.fold_constants(body);
+            }
+            Expression::Assign { value, .. }
+            | Expression::Return(value)
+            | Expression::OptionalFieldGet { object: value, .. } => {
+                self.fold_constants(value);
+            }
+            Expression::IfThenElse {
+                condition,
+                if_true,
+                if_false,
+            } => {
+                self.fold_constants(condition);
+                self.fold_constants(if_true);
+                self.fold_constants(if_false);
+            }
+            Expression::While { condition, body } => {
+                self.fold_constants(condition);
+                self.fold_constants(body);
+            }
+            Expression::Do(steps) => {
+                for step in steps {
+                    self.fold_constants(step);
+                }
+            }
+            Expression::Match {
+                subject,
+                arms,
+                default,
+            } => {
+                self.fold_constants(subject);
+                for (_, body) in arms {
+                    self.fold_constants(body);
+                }
+                if let Some(default) = default {
+                    self.fold_constants(default);
+                }
+            }
+            Expression::Closure { body, .. } => {
+                self.fold_constants(Rc::make_mut(body));
+            }
+            Expression::ListComprehension {
+                element,
+                iterable,
+                condition,
+                ..
+            } => {
+                self.fold_constants(element);
+                self.fold_constants(iterable);
+                if let Some(condition) = condition {
+                    self.fold_constants(condition);
+                }
+            }
+        }
+    }
 
-        Ok(())
+    /// Creates an object with its fields initialized, starting with the ones
+    /// inherited from the outermost superclass so that subclasses can give
+    /// them new initializers.
+    pub fn instantiate(&mut self, class: ClassID) -> Result<Value> {
+        let ancestry = self
+            .ancestry(Type::Object(class))
+            .filter_map(|typ| match typ {
+                Type::Object(class_id) => self.fields.get(&class_id).cloned(),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let mut properties = HashMap::new();
+        for fields in ancestry.iter().rev() {
+            for field in fields.iter() {
+                let value = self.evaluate_expression(&field.initializer)?;
+                properties.insert(field.name.clone(), value);
+            }
+        }
+        Ok(Value::Object(Rc::new(Object { class, properties })))
+    }
+
+    /// The prelude is part of the interpreter, so it failing to load is a
+    /// bug rather than something to report to the user.
+    fn load_prelude(&mut self) {
+        let program = parse::program(PRELUDE).expect("prelude should parse");
+        let program =
+            resolve::program(program, &mut resolve::Warnings::default())
+                .expect("prelude should resolve");
+        for class in program.classes {
+            let typ = Type::builtin(&class.name)
+                .expect("prelude classes should be named after builtin types");
+            self.define_methods(typ, class.methods);
+        }
+    }
+
+    fn define_methods(
+        &mut self,
+        typ: Type,
+        methods: Vec<ClassMethod<Expression>>,
+    ) {
+        self.method_cache.clear();
+        for method in methods {
+            let parameter_count = method.parameters.len();
+            self.custom_methods.insert(
+                (typ, method.name, parameter_count),
+                Rc::new(Method::Custom {
+                    parameter_count,
+                    body: method.body,
+                }),
+            );
+        }
+    }
+
+    pub fn run(&mut self, main_type: ClassID) -> Result<Value> {
+        let typ = Type::Object(main_type);
+        // `main` can optionally take the command line arguments as a list.
+        let (main_method, arguments) =
+            if let Some(method) = self.lookup_method(typ, "main", 1) {
+                (method, vec![Value::List(self.program_arguments())])
+            } else if let Some(method) = self.lookup_method(typ, "main", 0) {
+                (method, Vec::new())
+            } else if !self.custom_method_arities(typ, "main").is_empty() {
+                bail!("`main` must take at most one parameter");
+            } else {
+                bail!("program has no entry point");
+            };
+        let this = self.instantiate(main_type)?;
+        self.invoke_method(&main_method, this, arguments)
+    }
+
+    /// Runs the program with the given arguments, returning the result of
+    /// `main` along with everything the program printed. The output and the
+    /// program arguments are restored afterwards.
+    pub fn run_capturing(
+        &mut self,
+        main_type: ClassID,
+        arguments: Vec<Value>,
+    ) -> Result<(Value, String)> {
+        let buffer = SharedBuffer::default();
+        let output =
+            std::mem::replace(&mut self.output, Box::new(buffer.clone()));
+        let program_arguments =
+            std::mem::replace(&mut self.program_arguments, Rc::new(arguments));
+        let result = self.run(main_type);
+        self.output = output;
+        self.program_arguments = program_arguments;
+        let value = result?;
+        let printed = String::from_utf8(buffer.take())
+            .context("program output is not valid UTF-8")?;
+        Ok((value, printed))
+    }
+
+    /// Evaluates an expression outside of any method, with the local variables
+    /// pushed by `push_local_variable` in scope.
+    pub fn evaluate(&mut self, expression: &Expression) -> Result<Value> {
+        self.evaluate_expression(expression)
+    }
+
+    /// The names of every class loaded so far.
+    pub const fn class_names(&self) -> &ClassNames {
+        &self.class_names
+    }
+
+    pub fn push_local_variable(&mut self, value: Value) {
+        self.local_variables.push(value);
     }
 
-    pub fn new_class_id(&mut self) -> ClassID {
+    pub const fn new_class_id(&mut self) -> ClassID {
         self.class_id_counter += 1;
         ClassID(self.class_id_counter)
     }
 
+    /// Builtin methods check their own arguments, so only custom methods are
+    /// picked by `argument_count`.
+    fn lookup_method(
+        &mut self,
+        typ: Type,
+        name: &str,
+        argument_count: usize,
+    ) -> Option<Rc<Method>> {
+        let key = (typ, name, argument_count);
+        if let Some(method) = self.method_cache.get(&key as &dyn MethodKey) {
+            return Some(method.clone());
+        }
+        let method = self
+            .ancestry(typ)
+            .find_map(|typ| {
+                self.custom_methods
+                    .get(&(typ, name, argument_count) as &dyn MethodKey)
+            })
+            .or_else(|| {
+                self.methods.get(&typ).and_then(|methods| methods.get(name))
+            })
+            .or_else(|| match typ {
+                Type::Object(_) => self.object_methods.get(name),
+                _ => None,
+            })
+            .or_else(|| self.universal_methods.get(name))?
+            .clone();
+        self.method_cache
+            .insert((typ, name.to_owned(), argument_count), method.clone());
+        Some(method)
+    }
+
+    /// The numbers of arguments that the methods named `name` that `typ`
+    /// defines or inherits take, in ascending order.
+    fn custom_method_arities(&self, typ: Type, name: &str) -> Vec<usize> {
+        let ancestry = self.ancestry(typ).collect::<Vec<_>>();
+        let mut arities = self
+            .custom_methods
+            .keys()
+            .filter(|(method_type, method_name, _)| {
+                ancestry.contains(method_type) && method_name == name
+            })
+            .map(|(_, _, arity)| *arity)
+            .collect::<Vec<_>>();
+        arities.sort_unstable();
+        arities.dedup();
+        arities
+    }
+
+    /// `typ` followed by its superclass, that class's superclass and so on.
+    fn ancestry(&self, typ: Type) -> impl Iterator<Item = Type> + '_ {
+        std::iter::successors(Some(typ), |typ| match typ {
+            Type::Object(class_id) => {
+                self.superclasses.get(class_id).copied().map(Type::Object)
+            }
+            _ => None,
+        })
+    }
+
     fn invoke_method(
         &mut self,
         method: &Method,
@@ -89,90 +544,836 @@ impl VM {
         arguments: Vec<Value>,
     ) -> Result<Value> {
         match method {
-            Method::Builtin(f) => Ok(f(self, &this, &arguments)),
-            Method::Custom { body } => {
+            Method::Builtin(f) => f(self, &this, &arguments),
+            Method::Custom {
+                parameter_count,
+                body,
+            } => {
+                // Methods are looked up by how many arguments they take, so
+                // the parameters' De Bruijn indices line up with `arguments`.
+                debug_assert_eq!(arguments.len(), *parameter_count);
+                self.enter_call()?;
                 let local_variable_count = self.local_variables.len();
+                let frame_start = std::mem::replace(
+                    &mut self.frame_start,
+                    local_variable_count,
+                );
                 self.local_variables.push(this);
                 self.local_variables.extend(arguments);
-                let result = self.evaluate_expression(body);
+                // Self-recursive calls in tail position reuse this loop
+                // instead of growing the native stack.
+                let result = loop {
+                    match self.evaluate_in_tail_position(body, Some(method)) {
+                        Ok(Evaluated::TailCall { this, arguments }) => {
+                            self.local_variables.truncate(local_variable_count);
+                            self.local_variables.push(this);
+                            self.local_variables.extend(arguments);
+                        }
+                        Ok(Evaluated::Value(value)) => break Ok(value),
+                        Err(err)
+                            if err.downcast_ref()
+                                == Some(&ControlFlow::Return) =>
+                        {
+                            break Ok(std::mem::replace(
+                                &mut self.return_value,
+                                Value::Unit,
+                            ));
+                        }
+                        Err(err) => break Err(err),
+                    }
+                };
                 self.local_variables.truncate(local_variable_count);
+                self.frame_start = frame_start;
+                self.call_depth -= 1;
                 result
             }
         }
     }
 
+    pub fn call_closure(
+        &mut self,
+        closure: &Rc<Closure>,
+        arguments: Vec<Value>,
+    ) -> Result<Value> {
+        ensure!(
+            arguments.len() == closure.parameter_count,
+            "closure expected {} arguments, got {}",
+            closure.parameter_count,
+            arguments.len()
+        );
+        self.enter_call()?;
+        let local_variable_count = self.local_variables.len();
+        let frame_start =
+            std::mem::replace(&mut self.frame_start, local_variable_count);
+        self.local_variables
+            .extend(closure.captured.iter().cloned());
+        if let Some(slot) = closure.recursive_slot {
+            self.local_variables[local_variable_count + slot] =
+                Value::Closure(closure.clone());
+        }
+        self.local_variables.extend(arguments);
+        let result = match self.evaluate_expression(&closure.body) {
+            Err(err) if err.downcast_ref() == Some(&ControlFlow::Return) => {
+                Ok(std::mem::replace(&mut self.return_value, Value::Unit))
+            }
+            result => result,
+        };
+        self.local_variables.truncate(local_variable_count);
+        self.frame_start = frame_start;
+        self.call_depth -= 1;
+        result
+    }
+
+    /// Evaluates the closure bound by a `let rec`. The closure's own name is
+    /// captured as a placeholder that `call_closure` replaces with the closure.
+    fn evaluate_recursive_closure(&self, bound: &Expression) -> Result<Value> {
+        let Expression::Closure { parameters, body } = bound else {
+            bail!("`let rec` can only bind a closure");
+        };
+        let mut captured = self.local_variables[self.frame_start..].to_vec();
+        let recursive_slot = captured.len();
+        captured.push(Value::Unit);
+        Ok(Value::Closure(Rc::new(Closure {
+            parameter_count: parameters.len(),
+            captured,
+            recursive_slot: Some(recursive_slot),
+            body: body.clone(),
+        })))
+    }
+
+    /// Has to be paired with decrementing `call_depth` once the call is done.
+    fn enter_call(&mut self) -> Result<()> {
+        if self
+            .call_depth_limit
+            .is_some_and(|limit| self.call_depth >= limit)
+        {
+            bail!("call depth limit exceeded");
+        }
+        self.call_depth += 1;
+        Ok(())
+    }
+
     fn evaluate_expression(
         &mut self,
         expression: &Expression,
     ) -> Result<Value> {
-        Ok(match expression {
+        match self.evaluate_in_tail_position(expression, None)? {
+            Evaluated::Value(value) => Ok(value),
+            Evaluated::TailCall { .. } => {
+                unreachable!("tail call outside of any method")
+            }
+        }
+    }
+
+    /// `tail_of` is the method whose body `expression` is in tail position of,
+    /// if any. Calls to that same method are then returned as
+    /// `Evaluated::TailCall` rather than performed.
+    #[allow(clippy::too_many_lines)]
+    fn evaluate_in_tail_position(
+        &mut self,
+        expression: &Expression,
+        tail_of: Option<&Method>,
+    ) -> Result<Evaluated> {
+        self.steps += 1;
+        if self.step_limit.is_some_and(|limit| self.steps > limit) {
+            bail!("evaluation step limit exceeded");
+        }
+        Ok(Evaluated::Value(match expression {
             Expression::Literal(value) => value.clone(),
             Expression::MethodCall {
                 name,
                 this,
                 arguments,
+                line,
             } => {
                 let this = self.evaluate_expression(this)?;
-                let this_type = this.typ();
-                let method = self
-                    .methods
-                    .get(&this_type)
-                    .and_then(|methods| methods.get(name))
-                    .with_context(|| {
-                        format!(
-                            "type `{this_type}` has no method named `{name}`"
-                        )
-                    })?
-                    .clone();
-                let arguments = arguments
-                    .iter()
-                    .map(|argument| self.evaluate_expression(argument))
-                    .collect::<Result<_>>()?;
-                self.invoke_method(&method, this, arguments)?
+                let Some(method) =
+                    self.lookup_method(this.typ(), name, arguments.len())
+                else {
+                    return self
+                        .call_method_fallback(&this, name, arguments, *line)
+                        .map(Evaluated::Value);
+                };
+                let arguments = self.evaluate_arguments(arguments)?;
+                if tail_of.is_some_and(|tail_of| {
+                    std::ptr::eq(tail_of, Rc::as_ptr(&method))
+                }) {
+                    return Ok(Evaluated::TailCall { this, arguments });
+                }
+                let result = self.invoke_method(&method, this, arguments);
+                // Errors in custom methods already say where in the method
+                // body they happened, which is more useful than the call site.
+                if let Method::Builtin(_) = *method {
+                    result.with_context(|| format!("at line {line}"))?
+                } else {
+                    result?
+                }
             }
             Expression::LocalVariable {
                 name_or_de_bruijn_index: index,
-            } => self
-                .local_variables
-                .get(self.local_variables.len() - 1 - *index)
-                .with_context(|| {
-                    format!("De Bruijn index {index} is out of range")
-                })?
-                .clone(),
+            } => self.local_variable(*index)?.clone(),
             Expression::LetIn {
                 name: (),
+                is_mutable: _,
+                is_recursive,
                 bound,
                 body,
             } => {
-                let bound = self.evaluate_expression(bound)?;
+                let bound = if *is_recursive {
+                    self.evaluate_recursive_closure(bound)?
+                } else {
+                    self.evaluate_expression(bound)?
+                };
+                let local_variable_count = self.local_variables.len();
                 self.local_variables.push(bound);
-                let result = self.evaluate_expression(body)?;
-                self.local_variables.pop();
-                result
+                let result = self.evaluate_in_tail_position(body, tail_of);
+                self.local_variables.truncate(local_variable_count);
+                return result;
+            }
+            Expression::Assign {
+                name_or_de_bruijn_index: index,
+                value,
+            } => {
+                let value = self.evaluate_expression(value)?;
+                *self.local_variable(*index)? = value;
+                Value::Unit
             }
             Expression::IfThenElse {
                 condition,
                 if_true,
                 if_false,
             } => {
-                let Value::Bool(condition) =
-                    self.evaluate_expression(condition)?
-                else {
-                    todo!()
-                };
-                self.evaluate_expression(if condition {
-                    if_true
-                } else {
-                    if_false
-                })?
+                let condition =
+                    self.evaluate_expression(condition)?.as_bool()?;
+                return self.evaluate_in_tail_position(
+                    if condition { if_true } else { if_false },
+                    tail_of,
+                );
+            }
+            Expression::While { condition, body } => {
+                while self.evaluate_expression(condition)?.as_bool()? {
+                    if let Err(err) = self.evaluate_expression(body) {
+                        match err.downcast_ref() {
+                            Some(ControlFlow::Break) => break,
+                            Some(ControlFlow::Continue) => {}
+                            _ => return Err(err),
+                        }
+                    }
+                }
+                Value::Unit
+            }
+            Expression::Return(value) => {
+                self.return_value = self.evaluate_expression(value)?;
+                return Err(ControlFlow::Return.into());
             }
+            Expression::Break => return Err(ControlFlow::Break.into()),
+            Expression::Continue => return Err(ControlFlow::Continue.into()),
             Expression::Do(steps) => {
-                let mut res = Value::Unit;
-                for step in steps {
-                    res = self.evaluate_expression(step)?;
+                let Some((last, init)) = steps.split_last() else {
+                    return Ok(Evaluated::Value(Value::Unit));
+                };
+                for step in init {
+                    self.evaluate_expression(step)?;
                 }
-                res
+                return self.evaluate_in_tail_position(last, tail_of);
             }
-        })
+            Expression::Match {
+                subject,
+                arms,
+                default,
+            } => {
+                let subject = self.evaluate_expression(subject)?;
+                let body = arms
+                    .iter()
+                    .find(|(pattern, _)| *pattern == subject)
+                    .map(|(_, body)| body)
+                    .or(default.as_deref())
+                    .with_context(|| {
+                        format!(
+                            "no arm of `match` matches {}",
+                            subject.with_class_names(&self.class_names)
+                        )
+                    })?;
+                return self.evaluate_in_tail_position(body, tail_of);
+            }
+            Expression::New(class) => {
+                let class_id =
+                    *self.class_ids.get(class).with_context(|| {
+                        format!("class `{class}` is not defined")
+                    })?;
+                self.instantiate(class_id)?
+            }
+            Expression::Closure { parameters, body } => {
+                Value::Closure(Rc::new(Closure {
+                    parameter_count: parameters.len(),
+                    captured: self.local_variables[self.frame_start..].to_vec(),
+                    recursive_slot: None,
+                    body: body.clone(),
+                }))
+            }
+            Expression::ListComprehension {
+                element,
+                name: (),
+                iterable,
+                condition,
+            } => {
+                let iterable = self.evaluate_expression(iterable)?;
+                let mut elements = Vec::new();
+                for item in iterable.as_list()?.iter() {
+                    let local_variable_count = self.local_variables.len();
+                    self.local_variables.push(item.clone());
+                    let result = self.evaluate_comprehension_step(
+                        element,
+                        condition.as_deref(),
+                    );
+                    self.local_variables.truncate(local_variable_count);
+                    elements.extend(result?);
+                }
+                Value::List(Rc::new(elements))
+            }
+            Expression::OptionalFieldGet { object, field } => {
+                match self.evaluate_expression(object)? {
+                    Value::Unit => Value::Unit,
+                    object => object
+                        .as_object()?
+                        .properties
+                        .get(field)
+                        .cloned()
+                        .unwrap_or(Value::Unit),
+                }
+            }
+        }))
+    }
+
+    fn evaluate_arguments(
+        &mut self,
+        arguments: &[Expression],
+    ) -> Result<Vec<Value>> {
+        arguments
+            .iter()
+            .map(|argument| self.evaluate_expression(argument))
+            .collect()
+    }
+
+    fn call_method_fallback(
+        &mut self,
+        this: &Value,
+        name: &str,
+        arguments: &[Expression],
+        line: usize,
+    ) -> Result<Value> {
+        let typ = this.typ();
+        let type_name = typ.with_class_names(&self.class_names);
+        let arities = self.custom_method_arities(typ, name);
+        let message = if let Some((last, rest)) = arities.split_last() {
+            let expected = if rest.is_empty() {
+                last.to_string()
+            } else {
+                let rest = rest
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("{rest} or {last}")
+            };
+            let plural = if arities == [1] { "" } else { "s" };
+            format!(
+                "method `{name}` of type `{type_name}` expected {expected} \
+                 argument{plural}, got {}",
+                arguments.len()
+            )
+        } else {
+            format!("type `{type_name}` has no method named `{name}`")
+        };
+        let missing =
+            || anyhow!(message.clone()).context(format!("at line {line}"));
+        let fallback = self.method_fallback.clone().ok_or_else(missing)?;
+        let arguments = self.evaluate_arguments(arguments)?;
+        fallback(self, this, name, &arguments).map_or_else(
+            || Err(missing()),
+            |result| result.with_context(|| format!("at line {line}")),
+        )
+    }
+
+    fn evaluate_comprehension_step(
+        &mut self,
+        element: &Expression,
+        condition: Option<&Expression>,
+    ) -> Result<Option<Value>> {
+        if let Some(condition) = condition {
+            if !self.evaluate_expression(condition)?.as_bool()? {
+                return Ok(None);
+            }
+        }
+        self.evaluate_expression(element).map(Some)
+    }
+
+    /// The resolver only produces indices of variables that are in scope, so
+    /// an index being out of range means that the resolver and the VM disagree
+    /// about which variables are on the stack, or that a bundle was tampered
+    /// with. Either way it is an error rather than a panic.
+    fn local_variable(&mut self, index: usize) -> Result<&mut Value> {
+        let count = self.local_variables.len();
+        count
+            .checked_sub(1)
+            .and_then(|top| top.checked_sub(index))
+            .and_then(|slot| self.local_variables.get_mut(slot))
+            .with_context(|| {
+                format!(
+                    "De Bruijn index {index} is out of range for {count} \
+                     local variables"
+                )
+            })
+    }
+}
+
+/// Signals that unwind the evaluation of a method body. They are propagated as
+/// errors since every evaluation step already passes those on, and the value
+/// of a `return` is stored in the VM since errors have to be `Send`.
+#[derive(Debug, PartialEq, Eq)]
+enum ControlFlow {
+    Return,
+    Break,
+    Continue,
+}
+
+impl fmt::Display for ControlFlow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Return => f.write_str("`return` outside of a method"),
+            Self::Break => f.write_str("`break` outside of a loop"),
+            Self::Continue => f.write_str("`continue` outside of a loop"),
+        }
+    }
+}
+
+impl std::error::Error for ControlFlow {}
+
+enum Evaluated {
+    Value(Value),
+    TailCall { this: Value, arguments: Vec<Value> },
+}
+
+/// An output that can still be read after a clone of it has been given to
+/// `VM::with_output`.
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    /// Everything written so far, leaving the buffer empty.
+    pub fn take(&self) -> Vec<u8> {
+        self.0.take()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Lets `method_cache` and `custom_methods` be indexed by
+/// `(Type, &str, usize)` without allocating a `String` for every lookup.
+trait MethodKey {
+    fn key(&self) -> (Type, &str, usize);
+}
+
+impl MethodKey for (Type, String, usize) {
+    fn key(&self) -> (Type, &str, usize) {
+        (self.0, &self.1, self.2)
+    }
+}
+
+impl MethodKey for (Type, &str, usize) {
+    fn key(&self) -> (Type, &str, usize) {
+        *self
+    }
+}
+
+impl<'a> std::borrow::Borrow<dyn MethodKey + 'a> for (Type, String, usize) {
+    fn borrow(&self) -> &(dyn MethodKey + 'a) {
+        self
+    }
+}
+
+impl PartialEq for dyn MethodKey + '_ {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for dyn MethodKey + '_ {}
+
+impl std::hash::Hash for dyn MethodKey + '_ {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+/// Walks up from `class_id` through `superclasses`, returning the cycle it
+/// runs into if any, with its first class repeated at the end.
+fn inheritance_cycle(
+    class_id: ClassID,
+    superclasses: &HashMap<ClassID, ClassID>,
+) -> Option<Vec<ClassID>> {
+    let mut chain = vec![class_id];
+    while let Some(&superclass) = superclasses.get(&chain[chain.len() - 1]) {
+        let is_cycle = chain.contains(&superclass);
+        chain.push(superclass);
+        if is_cycle {
+            let start = chain.iter().position(|id| *id == superclass)?;
+            return Some(chain.split_off(start));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(mut vm: VM, source: &str) -> Result<Value> {
+        let program = parse::program(source)?;
+        let program =
+            resolve::program(program, &mut resolve::Warnings::default())?;
+        let class_ids = vm.load_program(program)?;
+        vm.run(crate::main_class(&class_ids)?)
+    }
+
+    #[test]
+    fn inheritance_cycles_are_not_loaded() {
+        // `resolve::program` rejects cycles, so one has to be put in after
+        // resolution, the way a hand-edited bundle could have one.
+        let source = "class A {} class B extends A {}";
+        let program = parse::program(source).expect("program should parse");
+        let mut program =
+            resolve::program(program, &mut resolve::Warnings::default())
+                .expect("program should resolve");
+        program.classes[0].superclass = Some("B".to_owned());
+        let mut vm = VM::sandboxed();
+        let err = vm.load_program(program).expect_err("A and B form a cycle");
+        assert_eq!(err.to_string(), "inheritance cycle: `A` -> `B` -> `A`");
+
+        let program =
+            parse::program("class C {}").expect("program should parse");
+        let mut program =
+            resolve::program(program, &mut resolve::Warnings::default())
+                .expect("program should resolve");
+        program.classes[0].superclass = Some("A".to_owned());
+        let err = vm.load_program(program).expect_err("A was never loaded");
+        assert_eq!(err.to_string(), "class `C` extends undefined class `A`");
+    }
+
+    #[test]
+    fn run_capturing_restores_the_program_arguments() {
+        let source = "class Main { def main = print (to_string (args ())); }";
+        let program = parse::program(source).expect("program should parse");
+        let program =
+            resolve::program(program, &mut resolve::Warnings::default())
+                .expect("program should resolve");
+        let mut vm = VM::sandboxed();
+        vm.set_program_arguments(vec!["outer".to_owned()]);
+        let class_ids = vm.load_program(program).expect("program should load");
+        let (_, printed) = vm
+            .run_capturing(
+                class_ids["Main"],
+                vec![Value::String("inner".into())],
+            )
+            .expect("program should run");
+        assert_eq!(printed, "[\"inner\"]");
+        assert!(matches!(
+            vm.program_arguments().as_slice(),
+            [Value::String(arg)] if &**arg == "outer"
+        ));
+    }
+
+    const DEEP_RECURSION: &str = "class Main {
+        def main = count this 100;
+        def count n = if (n == 0) { 0 } else { (count this (n - 1)) + 1 };
+    }";
+
+    #[test]
+    fn call_depth_limit_is_an_error() {
+        let vm = VM::sandboxed().with_call_depth_limit(50);
+        let err = run(vm, DEEP_RECURSION).expect_err("recursion is too deep");
+        assert_eq!(err.to_string(), "call depth limit exceeded");
+    }
+
+    #[test]
+    fn calls_within_the_call_depth_limit_succeed() {
+        let vm = VM::sandboxed().with_call_depth_limit(200);
+        assert!(matches!(run(vm, DEEP_RECURSION), Ok(Value::I32(100))));
+    }
+
+    #[test]
+    fn tail_calls_do_not_count_towards_the_call_depth_limit() {
+        let vm = VM::sandboxed().with_call_depth_limit(10);
+        let source = "class Main {
+            def main = count this 1000;
+            def count n = if (n == 0) { 0 } else { count this (n - 1) };
+        }";
+        assert!(matches!(run(vm, source), Ok(Value::I32(0))));
+    }
+
+    #[test]
+    fn println_writes_to_the_output() {
+        let output = SharedBuffer::default();
+        let source = r#"class Main {
+            def main = do { println "Hello"; print (to_string 42); println ""; };
+        }"#;
+        run(VM::with_output(output.clone()), source).expect("program runs");
+        assert_eq!(output.take(), b"Hello\n42\n");
+    }
+
+    #[test]
+    fn run_capturing_returns_the_output() {
+        let mut vm = VM::sandboxed();
+        let source = "class Main { def main args = println (to_string args); }";
+        let program = parse::program(source).expect("program parses");
+        let program =
+            resolve::program(program, &mut resolve::Warnings::default())
+                .expect("program resolves");
+        let class_ids = vm.load_program(program).expect("program loads");
+        let (value, printed) = vm
+            .run_capturing(class_ids["Main"], vec![Value::I32(1)])
+            .expect("program runs");
+        assert!(matches!(value, Value::Unit));
+        assert_eq!(printed, "[1]\n");
+    }
+
+    #[test]
+    fn out_of_range_de_bruijn_index_is_an_error() {
+        let mut vm = VM::sandboxed();
+        vm.push_local_variable(Value::I32(1));
+        let expression = Expression::LocalVariable {
+            name_or_de_bruijn_index: 3,
+        };
+        let err = vm.evaluate(&expression).expect_err("index is too big");
+        assert_eq!(
+            err.to_string(),
+            "De Bruijn index 3 is out of range for 1 local variables"
+        );
+    }
+
+    #[test]
+    fn de_bruijn_index_without_local_variables_is_an_error() {
+        let mut vm = VM::sandboxed();
+        let expression = Expression::LocalVariable {
+            name_or_de_bruijn_index: 0,
+        };
+        assert!(vm.evaluate(&expression).is_err());
+    }
+
+    #[test]
+    fn step_limit_is_an_error() {
+        let vm = VM::sandboxed().with_step_limit(1000);
+        let source = "class Main { def main = while (true) {}; }";
+        let err = run(vm, source).expect_err("loop never ends");
+        assert_eq!(err.to_string(), "evaluation step limit exceeded");
+    }
+
+    #[test]
+    fn run_capturing_returns_the_value_and_the_output() {
+        let mut vm = VM::new();
+        let source = r#"class Main {
+            def main = do { println "printed"; 42 };
+        }"#;
+        let program = parse::program(source).expect("program parses");
+        let program =
+            resolve::program(program, &mut resolve::Warnings::default())
+                .expect("program resolves");
+        let class_ids = vm.load_program(program).expect("program loads");
+        let (value, printed) = vm
+            .run_capturing(class_ids["Main"], Vec::new())
+            .expect("program runs");
+        assert!(matches!(value, Value::I32(42)));
+        assert_eq!(printed, "printed\n");
+    }
+
+    #[test]
+    fn method_fallback_handles_unknown_methods() {
+        let mut vm = VM::sandboxed();
+        vm.set_method_fallback(Box::new(|_vm, this, name, arguments| {
+            match (this, name, arguments) {
+                (Value::I32(n), "double", []) => Some(Ok(Value::I32(n * 2))),
+                _ => None,
+            }
+        }));
+        let source = "class Main { def main = double 21; }";
+        assert!(matches!(run(vm, source), Ok(Value::I32(42))));
+    }
+
+    #[test]
+    fn method_fallback_can_fall_through() {
+        let mut vm = VM::sandboxed();
+        vm.set_method_fallback(Box::new(|_vm, _this, _name, _arguments| None));
+        let source = "class Main { def main = double 21; }";
+        let err = run(vm, source).expect_err("nothing handles `double`");
+        assert!(format!("{err:#}").contains("no method named `double`"));
+    }
+
+    #[test]
+    fn eprint_returns_unit_without_touching_the_output() {
+        let output = SharedBuffer::default();
+        let mut vm = VM::with_output(output.clone());
+        vm.error_output = Box::new(io::sink());
+        let source = r#"class Main {
+            def main = do { eprintln "to stderr"; eprint "again" };
+        }"#;
+        assert!(matches!(run(vm, source), Ok(Value::Unit)));
+        assert!(output.take().is_empty());
+    }
+
+    /// A tiny pseudo-random number generator, so that the generated programs
+    /// are the same on every run.
+    struct Xorshift(u64);
+
+    impl Xorshift {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            usize::try_from(self.0 % n as u64).expect("below n")
+        }
+    }
+
+    /// Generates an `I32` expression that only refers to the variables in
+    /// `scope`, nesting bindings, closures and method calls so that De Bruijn
+    /// indices get exercised across frames.
+    fn expression(
+        rng: &mut Xorshift,
+        scope: &mut Vec<String>,
+        depth: usize,
+    ) -> String {
+        let choice = if depth == 0 {
+            rng.below(2)
+        } else {
+            rng.below(8)
+        };
+        match choice {
+            0 => rng.below(10).to_string(),
+            1 if scope.is_empty() => "(this_value this)".to_owned(),
+            1 => scope[rng.below(scope.len())].clone(),
+            2 | 3 => {
+                let name = format!("v{}", scope.len());
+                let bound = expression(rng, scope, depth - 1);
+                let keyword = if rng.below(2) == 0 { "let" } else { "var" };
+                scope.push(name.clone());
+                let body = expression(rng, scope, depth - 1);
+                scope.pop();
+                format!("({keyword} {name} = {bound} in {body})")
+            }
+            4 => {
+                let parameter = format!("v{}", scope.len());
+                scope.push(parameter.clone());
+                let body = expression(rng, scope, depth - 1);
+                scope.pop();
+                let argument = expression(rng, scope, depth - 1);
+                format!("(apply (fn {parameter} -> {body}) {argument})")
+            }
+            5 => {
+                let (a, b) = (
+                    expression(rng, scope, depth - 1),
+                    expression(rng, scope, depth - 1),
+                );
+                format!("(pick this {a} {b})")
+            }
+            6 => {
+                let condition = expression(rng, scope, depth - 1);
+                let (a, b) = (
+                    expression(rng, scope, depth - 1),
+                    expression(rng, scope, depth - 1),
+                );
+                format!("(if ({condition} < 5) {{ {a} }} else {{ {b} }})")
+            }
+            _ => {
+                let (a, b) = (
+                    expression(rng, scope, depth - 1),
+                    expression(rng, scope, depth - 1),
+                );
+                format!("(wrapping_add {a} {b})")
+            }
+        }
+    }
+
+    #[test]
+    fn de_bruijn_indices_of_generated_programs_are_in_range() {
+        let mut rng = Xorshift(0x2545_f491_4f6c_dd1d);
+        for _ in 0..300 {
+            let main = expression(&mut rng, &mut Vec::new(), 5);
+            let source = format!(
+                "class Main {{
+                    def main = {main};
+                    def pick a b = if (a < b) {{ b }} else {{ a }};
+                    def this_value = 3;
+                }}"
+            );
+            let result = run(VM::sandboxed(), &source);
+            assert!(
+                matches!(result, Ok(Value::I32(_))),
+                "{source}\n{result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn prelude_methods_can_be_called() {
+        let source = "class Main { def main = max3 1 7 3; }";
+        assert!(matches!(run(VM::sandboxed(), source), Ok(Value::I32(7))));
+        let err = run(VM::without_prelude(io::sink()), source)
+            .expect_err("`max3` is only in the prelude");
+        assert!(format!("{err:#}").contains("no method named `max3`"));
+    }
+
+    fn folded(source: &str) -> Expression {
+        let expression =
+            parse::single_expression(source).expect("expression should parse");
+        let mut expression = resolve::Resolver::default()
+            .resolve_expression(expression)
+            .expect("expression should resolve");
+        VM::sandboxed().fold_constants(&mut expression);
+        expression
+    }
+
+    #[test]
+    fn literal_arithmetic_is_folded() {
+        assert!(matches!(
+            folded("1 + 2 * 3"),
+            Expression::Literal(Value::I32(7))
+        ));
+        assert!(matches!(
+            folded("if (1 < 2) { 3 } else { 4 }"),
+            Expression::IfThenElse { condition, .. }
+                if matches!(*condition, Expression::Literal(Value::Bool(true)))
+        ));
+    }
+
+    #[test]
+    fn failing_and_impure_calls_are_not_folded() {
+        assert!(matches!(folded("1 / 0"), Expression::MethodCall { .. }));
+        assert!(matches!(
+            folded(r#"println "hi""#),
+            Expression::MethodCall { .. }
+        ));
+    }
+
+    #[test]
+    fn sandboxed_output_goes_nowhere() {
+        let source = r#"class Main { def main = println "hi"; }"#;
+        assert!(matches!(run(VM::sandboxed(), source), Ok(Value::Unit)));
+    }
+
+    #[test]
+    fn sandboxed_file_access_is_an_error() {
+        let source = r#"class Main { def main = read_file "Cargo.toml"; }"#;
+        let err = run(VM::sandboxed(), source).expect_err("sandboxed");
+        assert!(format!("{err:#}").contains("cannot read files in a sandbox"));
+        let source = r#"class Main { def main = write_file "x" ""; }"#;
+        let err = run(VM::sandboxed(), source).expect_err("sandboxed");
+        assert!(format!("{err:#}").contains("cannot write files in a sandbox"));
     }
 }
@@ -0,0 +1,558 @@
+//! Tests of the builtin methods of each type.
+
+mod common;
+
+use common::{eval, eval_err, run, run_err, show, temp_path};
+use ol::value::Value;
+
+#[test]
+fn clamp_i32() {
+    assert_eq!(eval("clamp -5 0 10"), Value::I32(0));
+    assert_eq!(eval("clamp 5 0 10"), Value::I32(5));
+    assert_eq!(eval("clamp 15 0 10"), Value::I32(10));
+    assert!(eval_err("clamp 5 10 0")
+        .contains("lower bound 10 of `clamp` is greater than upper bound 0"));
+}
+
+#[test]
+fn clamp_f64() {
+    assert_eq!(eval("clamp -5.0 0.0 1.5"), Value::F64(0.0));
+    assert_eq!(eval("clamp 0.5 0.0 1.5"), Value::F64(0.5));
+    assert_eq!(eval("clamp 2.0 0.0 1.5"), Value::F64(1.5));
+    assert!(eval_err("clamp 0.5 1.5 0.0").contains("lower bound 1.5"));
+}
+
+#[test]
+fn clamp_f64_nan() {
+    let Value::F64(clamped) = eval("clamp (0.0 / 0.0) 0.0 1.0") else {
+        panic!("`clamp` returns an F64");
+    };
+    assert!(clamped.is_nan());
+    assert!(eval_err("clamp 0.5 0.0 (0.0 / 0.0)").contains("lower bound"));
+}
+
+#[test]
+fn reverse() {
+    assert_eq!(show(r#"reverse "abc""#), "cba");
+    assert_eq!(show(r#"reverse "héllo""#), "olléh");
+    assert_eq!(show(r#"reverse """#), "");
+}
+
+#[test]
+fn eq_compares_builtin_types_by_value() {
+    for (a, b, expected) in [
+        ("1", "1", true),
+        ("1", "2", false),
+        ("true", "true", true),
+        ("true", "false", false),
+        (r#""a""#, r#""a""#, true),
+        (r#""a""#, r#""b""#, false),
+        ("()", "()", true),
+        ("1.5", "1.5", true),
+        ("range 0 3", "range 0 3", true),
+        ("range 0 3", "range 0 2", false),
+    ] {
+        assert_eq!(eval(&format!("eq ({a}) ({b})")), Value::Bool(expected));
+        assert_eq!(eval(&format!("ne ({a}) ({b})")), Value::Bool(!expected));
+    }
+}
+
+#[test]
+fn eq_compares_objects_by_identity() {
+    let source = "class Main {
+        def main = let a = new Point, b = new Point in
+            do { assert (eq a a); assert (ne a b); a == b };
+    }
+    class Point { field x = 1; }";
+    assert_eq!(
+        ol::run_source(source).expect("program runs"),
+        Value::Bool(false)
+    );
+}
+
+#[test]
+fn eq_rejects_different_types() {
+    assert!(
+        eval_err(r#"eq 1 "1""#).contains("cannot compare `I32` with `String`")
+    );
+}
+
+#[test]
+fn case_conversion() {
+    assert_eq!(show(r#"to_upper "Hello, World!""#), "HELLO, WORLD!");
+    assert_eq!(show(r#"to_lower "Hello, World!""#), "hello, world!");
+    assert_eq!(show(r#"to_upper "straße""#), "STRASSE");
+    assert_eq!(show(r#"to_lower "ÄÖÜ""#), "äöü");
+}
+
+#[test]
+fn substring_predicates() {
+    for (expression, expected) in [
+        (r#"contains "hello" "ell""#, true),
+        (r#"contains "hello" "xyz""#, false),
+        (r#"contains "hello" """#, true),
+        (r#"starts_with "hello" "he""#, true),
+        (r#"starts_with "hello" "lo""#, false),
+        (r#"starts_with "hello" """#, true),
+        (r#"ends_with "hello" "lo""#, true),
+        (r#"ends_with "hello" "he""#, false),
+        (r#"ends_with "hello" """#, true),
+    ] {
+        assert_eq!(eval(expression), Value::Bool(expected), "{expression}");
+    }
+}
+
+#[test]
+fn trimming() {
+    assert_eq!(show(r#"trim "  hi  ""#), "hi");
+    assert_eq!(show(r#"trim_start "  hi  ""#), "hi  ");
+    assert_eq!(show(r#"trim_end "  hi  ""#), "  hi");
+    assert_eq!(show(r#"trim "\thi\t\n""#), "hi");
+    assert_eq!(show(r#"trim " \t \n ""#), "");
+}
+
+#[test]
+fn repeat() {
+    assert_eq!(show(r#"repeat "ab" 3"#), "ababab");
+    assert_eq!(show(r#"repeat "x" 0"#), "");
+    assert!(eval_err(r#"repeat "x" -1"#)
+        .contains("cannot repeat a string -1 times"));
+}
+
+#[test]
+fn reverse_keeps_non_ascii_characters_intact() {
+    assert_eq!(show(r#"reverse "añb""#), "bña");
+    assert_eq!(show(r#"reverse "日本""#), "本日");
+}
+
+#[test]
+fn parse_i32() {
+    assert_eq!(eval(r#"parse_i32 "42""#), Value::I32(42));
+    assert_eq!(eval(r#"parse_i32 "-7""#), Value::I32(-7));
+    assert!(
+        eval_err(r#"parse_i32 "foo""#).contains(r#"cannot parse "foo" as I32"#)
+    );
+    assert!(eval_err(r#"parse_i32 "2147483648""#)
+        .contains(r#"cannot parse "2147483648" as I32"#));
+    assert!(eval_err(r#"parse_i32 " 1""#).contains("cannot parse"));
+}
+
+#[test]
+fn abs_min_max() {
+    assert_eq!(eval("abs -5"), Value::I32(5));
+    assert_eq!(eval("abs 5"), Value::I32(5));
+    assert_eq!(eval("abs (-2147483647)"), Value::I32(i32::MAX));
+    assert!(eval_err("abs -2147483648").contains("I32 overflow in abs"));
+    assert_eq!(eval("min 3 -4"), Value::I32(-4));
+    assert_eq!(eval("max 3 -4"), Value::I32(3));
+    assert_eq!(eval("min -2147483648 0"), Value::I32(i32::MIN));
+}
+
+#[test]
+fn strings_are_unchanged_by_being_shared() {
+    assert_eq!(
+        show(r#"let s = "abc" in let t = s in concat s (to_upper t) s"#),
+        "abcABCabc"
+    );
+    assert_eq!(
+        eval(r#"let s = "abc" in eq s (concat "a" "bc")"#),
+        Value::Bool(true)
+    );
+}
+
+#[test]
+fn assertions() {
+    assert_eq!(
+        eval("do { assert (1 + 1 == 2); assert_eq (2 * 3) 6 }"),
+        Value::Unit
+    );
+    assert!(eval_err("assert (1 == 2)").contains("assertion failed"));
+    assert!(eval_err("assert_eq (2 * 3) 7")
+        .contains("assertion failed: `6` is not equal to `7`"));
+}
+
+#[test]
+fn fields_lists_the_fields_of_an_object() {
+    let source = r#"class Main {
+        def main = concat (to_string (fields (new Point))) (to_string (fields this));
+    }
+    class Point { field y = 2; field x = 1; }"#;
+    assert_eq!(run(source), Value::String(r#"["x", "y"][]"#.into()));
+}
+
+#[test]
+fn map_insert_get_overwrite_and_remove() {
+    assert_eq!(
+        eval(r#"let m = map () in do { insert m "a" 1; get m "a" }"#),
+        Value::I32(1)
+    );
+    assert_eq!(
+        eval(
+            r#"let m = map () in do { insert m "a" 1; insert m "a" 2; get m "a" }"#
+        ),
+        Value::I32(2)
+    );
+    assert_eq!(
+        show(
+            r#"let m = map () in do { insert m "b" 1; insert m "a" 2; keys m }"#
+        ),
+        r#"["a", "b"]"#
+    );
+    assert_eq!(
+        eval(
+            r#"let m = map () in do { insert m "a" 1; remove m "a"; contains m "a" }"#
+        ),
+        Value::Bool(false)
+    );
+}
+
+#[test]
+fn map_missing_keys() {
+    assert_eq!(eval(r#"contains (map ()) "a""#), Value::Bool(false));
+    assert!(eval_err(r#"get (map ()) "a""#).contains(r#"map has no key "a""#));
+    assert!(
+        eval_err(r#"remove (map ()) "a""#).contains(r#"map has no key "a""#)
+    );
+}
+
+#[test]
+fn maps_are_shared_between_copies() {
+    assert_eq!(
+        show(r#"let m = map () in let n = m in do { insert n "a" 1; m }"#),
+        r#"{"a": 1}"#
+    );
+}
+
+#[test]
+fn cyclic_maps_can_be_shown() {
+    assert_eq!(
+        show(r#"let m = map () in do { insert m "self" m; to_string m }"#),
+        r#"{"self": {...}}"#
+    );
+}
+
+#[test]
+fn typeof_names_every_type() {
+    for (expression, typ) in [
+        ("()", "Unit"),
+        ("true", "Bool"),
+        ("1", "I32"),
+        ("1i64", "I64"),
+        ("1.0", "F64"),
+        (r#""s""#, "String"),
+        ("range 0 1", "List"),
+        ("map ()", "Map"),
+        ("fn -> 1", "Closure"),
+    ] {
+        assert_eq!(show(&format!("typeof ({expression})")), typ);
+    }
+}
+
+#[test]
+fn typeof_names_the_class_of_an_object() {
+    let source =
+        "class Main { def main = concat (typeof this) (typeof (new Point)); }
+    class Point {}";
+    assert_eq!(run(source), Value::String("MainPoint".into()));
+}
+
+#[test]
+fn is_empty() {
+    assert_eq!(eval(r#"is_empty """#), Value::Bool(true));
+    assert_eq!(eval(r#"is_empty "a""#), Value::Bool(false));
+    assert_eq!(eval("is_empty (range 0 0)"), Value::Bool(true));
+    assert_eq!(eval("is_empty (range 0 1)"), Value::Bool(false));
+}
+
+#[test]
+fn at_indexes_by_character() {
+    assert_eq!(show(r#"at "héllo" 0"#), "h");
+    assert_eq!(show(r#"at "héllo" 1"#), "é");
+    assert_eq!(show(r#"at "héllo" 4"#), "o");
+    assert!(eval_err(r#"at "héllo" 5"#)
+        .contains(r#"index 5 is out of range for "héllo""#));
+    assert!(eval_err(r#"at "héllo" -1"#).contains("index -1 is out of range"));
+}
+
+#[test]
+fn index_of() {
+    assert_eq!(eval(r#"index_of "hello" "he""#), Value::I32(0));
+    assert_eq!(eval(r#"index_of "hello" "ll""#), Value::I32(2));
+    assert_eq!(eval(r#"index_of "héllo" "l""#), Value::I32(2));
+    assert_eq!(eval(r#"index_of "hello" "x""#), Value::I32(-1));
+}
+
+#[test]
+fn substring() {
+    assert_eq!(show(r#"substring "hello" 1 3"#), "ell");
+    assert_eq!(show(r#"substring "héllo" 1 2"#), "él");
+    assert_eq!(show(r#"substring "hello" 2 0"#), "");
+    assert_eq!(show(r#"substring "hello" 5 0"#), "");
+    assert!(eval_err(r#"substring "hello" 3 5"#).contains("out of range"));
+    assert!(eval_err(r#"substring "hello" 6 0"#).contains("out of range"));
+    assert!(eval_err(r#"substring "hello" -1 2"#).contains("out of range"));
+    assert!(eval_err(r#"substring "hello" 1 -2"#).contains("out of range"));
+}
+
+#[test]
+fn i32_arithmetic_overflow_is_an_error() {
+    assert_eq!(eval("2147483646 + 1"), Value::I32(i32::MAX));
+    assert!(eval_err("2147483647 + 1").contains("I32 overflow in add"));
+    assert_eq!(eval("-2147483647 - 1"), Value::I32(i32::MIN));
+    assert!(eval_err("-2147483648 - 1").contains("I32 overflow in sub"));
+    assert!(eval_err("2147483647 * 2").contains("I32 overflow in mul"));
+    assert!(eval_err("-2147483648 * -1").contains("I32 overflow in mul"));
+    assert!(eval_err("-2147483648 / -1").contains("I32 overflow in div"));
+    assert!(eval_err("1 / 0").contains("division by zero"));
+}
+
+#[test]
+fn wrapping_arithmetic() {
+    assert_eq!(eval("wrapping_add 2147483647 1"), Value::I32(i32::MIN));
+    assert_eq!(eval("wrapping_sub -2147483648 1"), Value::I32(i32::MAX));
+    assert_eq!(eval("wrapping_mul 2147483647 2"), Value::I32(-2));
+    assert_eq!(eval("wrapping_add 1 2"), Value::I32(3));
+}
+
+#[test]
+fn bitwise_operations() {
+    assert_eq!(eval("bitand 12 10"), Value::I32(8));
+    assert_eq!(eval("bitor 12 10"), Value::I32(14));
+    assert_eq!(eval("bitxor 12 10"), Value::I32(6));
+    assert_eq!(eval("bitnot 0"), Value::I32(-1));
+    assert_eq!(eval("shl 1 4"), Value::I32(16));
+    assert_eq!(eval("shr 16 4"), Value::I32(1));
+    assert_eq!(eval("shr -16 2"), Value::I32(-4));
+}
+
+#[test]
+fn shifts_out_of_range_are_errors() {
+    assert!(eval_err("shl 1 32").contains("cannot shift an I32 by 32 bits"));
+    assert!(eval_err("shr 1 -1").contains("cannot shift an I32 by -1 bits"));
+    assert_eq!(eval("shl 1 31"), Value::I32(i32::MIN));
+}
+
+#[test]
+fn list_map() {
+    assert_eq!(show("map (range 1 4) (fn x -> x + 1)"), "[2, 3, 4]");
+    assert_eq!(show("map (range 0 0) (fn x -> x + 1)"), "[]");
+    assert!(eval_err("map (range 0 3) (fn x -> 1 / x)")
+        .contains("division by zero"));
+}
+
+#[test]
+fn list_filter_and_fold() {
+    assert_eq!(show("filter (range 1 5) (fn x -> x % 2 == 0)"), "[2, 4]");
+    assert_eq!(
+        eval("fold (range 1 4) 0 (fn sum x -> sum + x)"),
+        Value::I32(6)
+    );
+    assert_eq!(show(r#"fold (range 1 4) "" (fn s x -> concat s x)"#), "123");
+}
+
+#[test]
+fn range() {
+    assert_eq!(show("range 0 3"), "[0, 1, 2]");
+    assert_eq!(show("range 3 3"), "[]");
+    assert_eq!(show("range 5 2"), "[]");
+    assert_eq!(show("range -2 1"), "[-2, -1, 0]");
+}
+
+#[test]
+fn debug_dumps_nested_objects() {
+    let source = r#"class Main { def main = debug (new Line); }
+    class Line { field start = new Point; field name = "l"; }
+    class Point { field x = 1; field y = 2; }"#;
+    let expected = r#"Line {
+    name: "l",
+    start: Point {
+        x: 1,
+        y: 2,
+    },
+}"#;
+    assert_eq!(run(source), Value::String(expected.into()));
+}
+
+#[test]
+fn error_aborts_with_the_message() {
+    assert!(eval_err(r#"do { error "boom"; 1 }"#).ends_with("boom"));
+}
+
+#[test]
+fn chars() {
+    assert_eq!(show(r#"chars "abc""#), r#"["a", "b", "c"]"#);
+    assert_eq!(show(r#"chars "héé""#), r#"["h", "é", "é"]"#);
+    assert_eq!(show(r#"chars """#), "[]");
+}
+
+#[test]
+fn join() {
+    assert_eq!(show(r#"join (chars "abc") ", ""#), "a, b, c");
+    assert_eq!(show(r#"join (chars "a") "-""#), "a");
+    assert_eq!(show(r#"join (range 0 0) "-""#), "");
+}
+
+#[test]
+fn join_requires_string_elements() {
+    assert!(eval_err(r#"join (range 0 2) ", ""#)
+        .contains("expected `String`, got `I32`"));
+}
+
+#[test]
+fn concat_converts_other_values_to_strings() {
+    assert_eq!(show(r#"concat "x = " 1"#), "x = 1");
+    assert_eq!(show(r#"concat "" true () 1.5 "!""#), "true()1.5!");
+    assert_eq!(show(r#"concat "list: " (range 0 2)"#), "list: [0, 1]");
+    assert_eq!(show(r#"concat "a""#), "a");
+}
+
+#[test]
+fn i64_literals_and_arithmetic() {
+    assert_eq!(eval("5_000_000_000i64"), Value::I64(5_000_000_000));
+    assert_eq!(eval("2147483647i64 + 1i64"), Value::I64(2_147_483_648));
+    assert_eq!(eval("-3i64 * 4i64"), Value::I64(-12));
+    assert_eq!(show("typeof 1i64"), "I64");
+    assert_eq!(eval("1i64 < 2i64"), Value::Bool(true));
+}
+
+#[test]
+fn i64_overflow_is_an_error() {
+    assert!(eval_err("9223372036854775807i64 + 1i64")
+        .contains("I64 overflow in add"));
+}
+
+#[test]
+fn i32_and_i64_do_not_mix() {
+    assert!(eval_err("1i64 + 1").contains("expected `I64`, got `I32`"));
+}
+
+#[test]
+fn numeric_conversions() {
+    assert_eq!(eval("to_f64 3"), Value::F64(3.0));
+    assert_eq!(eval("to_i64 -3"), Value::I64(-3));
+    assert_eq!(eval("to_i32 2.9"), Value::I32(2));
+    assert_eq!(eval("to_i32 -2.9"), Value::I32(-2));
+    assert_eq!(eval("to_i32 42i64"), Value::I32(42));
+}
+
+#[test]
+fn conversions_out_of_range_are_errors() {
+    assert!(eval_err("to_i32 5_000_000_000i64")
+        .contains("5000000000 is out of range for I32"));
+    assert!(eval_err("to_i32 3.0e10").contains("out of range for I32"));
+    assert!(eval_err("to_i32 (div 0.0 0.0)")
+        .contains("NaN is out of range for I32"));
+}
+
+#[test]
+fn f64_arithmetic() {
+    assert_eq!(eval("1.5 + 2.25"), Value::F64(3.75));
+    assert_eq!(eval("1.0 / 4.0"), Value::F64(0.25));
+    assert_eq!(eval("sqrt 9.0"), Value::F64(3.0));
+    assert_eq!(eval("round 2.5"), Value::F64(3.0));
+    assert_eq!(eval("1.5 < 2.0"), Value::Bool(true));
+    assert_eq!(show("2.0"), "2.0");
+}
+
+#[test]
+fn f64_division_by_zero_follows_ieee_754() {
+    assert_eq!(eval("div 1.0 0.0"), Value::F64(f64::INFINITY));
+    assert_eq!(eval("div -1.0 0.0"), Value::F64(f64::NEG_INFINITY));
+    assert!(matches!(eval("div 0.0 0.0"), Value::F64(x) if x.is_nan()));
+}
+
+#[test]
+fn objects_are_shown_with_their_class_and_fields() {
+    let source = r#"class Main {
+        def main = concat (to_string (new Point)) " " (new Empty);
+    }
+    class Point { field y = 2; field x = "one"; }
+    class Empty {}"#;
+    assert_eq!(
+        run(source),
+        Value::String(r#"Point { x: "one", y: 2 } Empty {}"#.into())
+    );
+}
+
+#[test]
+fn unicode_escapes() {
+    assert_eq!(show(r#""\u{1F600}""#), "\u{1F600}");
+    assert_eq!(show(r#""é\x41""#), "éA");
+}
+
+#[test]
+fn invalid_unicode_escapes_are_syntax_errors() {
+    let source = r#"class Main { def main = "\u{D800}"; }"#;
+    let err = run_err(source);
+    assert!(
+        err.contains(r"invalid Unicode escape \u{D800}: surrogate code point"),
+        "{err}"
+    );
+    let source = r#"class Main { def main = "\u{110000}"; }"#;
+    let err = run_err(source);
+    assert!(
+        err.contains(
+            r"invalid Unicode escape \u{110000}: code point out of range"
+        ),
+        "{err}"
+    );
+}
+
+#[test]
+fn raw_strings_have_no_escapes() {
+    assert_eq!(show(r#"r"a\nb""#), r"a\nb");
+    assert_eq!(show(r##"r#"say "hi""#"##), r#"say "hi""#);
+    assert_eq!(show(r###"r##"a "# b"##"###), r##"a "# b"##);
+}
+
+#[test]
+fn triple_quoted_strings_keep_newlines_and_quotes() {
+    let source = "class Main { def main = \"\"\"line 1
+  \"line\" 2\\t!
+\"\"\"; }";
+    assert_eq!(
+        run(source),
+        Value::String("line 1\n  \"line\" 2\t!\n".into())
+    );
+}
+
+#[test]
+fn lines() {
+    assert_eq!(show(r#"lines "a\nb""#), r#"["a", "b"]"#);
+    assert_eq!(show(r#"lines "a\r\nb\n""#), r#"["a", "b"]"#);
+    assert_eq!(show(r#"lines "a\n\nb""#), r#"["a", "", "b"]"#);
+    assert_eq!(show(r#"lines """#), "[]");
+}
+
+#[test]
+fn read_file() {
+    let path = temp_path("read_file", "input.txt");
+    std::fs::write(&path, "contents\n").expect("file can be written");
+    let expression = format!("read_file {:?}", path.display().to_string());
+    assert_eq!(eval(&expression), Value::String("contents\n".into()));
+}
+
+#[test]
+fn reading_a_missing_file_is_an_error() {
+    let path = temp_path("read_file_missing", "missing.txt");
+    let expression = format!("read_file {:?}", path.display().to_string());
+    assert!(eval_err(&expression).contains("failed to read"));
+}
+
+#[test]
+fn write_file_creates_and_truncates() {
+    let path = temp_path("write_file", "output.txt");
+    let path_literal = format!("{:?}", path.display().to_string());
+    let expression = format!(
+        r#"do {{ write_file {path_literal} "long contents"; write_file {path_literal} "short"; read_file {path_literal} }}"#
+    );
+    assert_eq!(eval(&expression), Value::String("short".into()));
+}
+
+#[test]
+fn writing_into_a_missing_directory_is_an_error() {
+    let path = temp_path("write_file_missing", "missing").join("output.txt");
+    let expression =
+        format!(r#"write_file {:?} """#, path.display().to_string());
+    assert!(eval_err(&expression).contains("failed to write"));
+}
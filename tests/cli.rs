@@ -0,0 +1,245 @@
+//! Tests of the `ol` binary and its flags.
+
+mod common;
+
+use common::{ol, source_file, stderr, stdout, temp_path};
+use std::collections::HashMap;
+
+#[test]
+fn trailing_arguments_are_passed_to_the_program() {
+    let path = source_file(
+        "trailing_arguments",
+        "main.ol",
+        "class Main { def main = println (to_string (args ())); }",
+    );
+    let output = ol([path.as_os_str(), "foo".as_ref(), "bar".as_ref()]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "[\"foo\", \"bar\"]\n");
+}
+
+#[test]
+fn main_returning_an_i32_sets_the_exit_code() {
+    let path =
+        source_file("exit_code", "main.ol", "class Main { def main = 3; }");
+    assert_eq!(ol([&path]).status.code(), Some(3));
+}
+
+#[test]
+fn main_returning_anything_else_exits_successfully() {
+    for (file_name, result) in [("unit.ol", "()"), ("string.ol", "\"done\"")] {
+        let path = source_file(
+            "exit_code_other",
+            file_name,
+            &format!("class Main {{ def main = {result}; }}"),
+        );
+        assert_eq!(ol([&path]).status.code(), Some(0), "{result}");
+    }
+}
+
+#[test]
+fn exit_code_out_of_range_is_an_error() {
+    let path = source_file(
+        "exit_code_out_of_range",
+        "main.ol",
+        "class Main { def main = 256; }",
+    );
+    let output = ol([&path]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("exit code 256 is out of range"));
+}
+
+#[test]
+fn bundle_runs_like_the_source() {
+    let path = source_file(
+        "bundle",
+        "main.ol",
+        r#"class Main {
+            def main = println (concat "sum: " (add this 1 2));
+            def add a b = a + b;
+        }"#,
+    );
+    let bundle = temp_path("bundle", "main.olc");
+    let bundled =
+        ol(["--bundle".as_ref(), bundle.as_os_str(), path.as_os_str()]);
+    assert!(bundled.status.success());
+    assert_eq!(stdout(&bundled), "");
+
+    let from_source = ol([&path]);
+    let from_bundle = ol(["--run-bundle".as_ref(), bundle.as_os_str()]);
+    assert!(from_bundle.status.success());
+    assert_eq!(stdout(&from_bundle), "sum: 3\n");
+    assert_eq!(stdout(&from_bundle), stdout(&from_source));
+}
+
+#[test]
+fn running_a_source_file_as_a_bundle_is_an_error() {
+    let path = source_file("not_a_bundle", "main.ol", "class Main {}");
+    let output = ol(["--run-bundle".as_ref(), path.as_os_str()]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn main_can_take_the_arguments() {
+    let path = source_file(
+        "main_arguments",
+        "main.ol",
+        r#"class Main { def main args = println (join args ","); }"#,
+    );
+    let output =
+        ol([path.as_os_str(), "a".as_ref(), "b c".as_ref(), "d".as_ref()]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "a,b c,d\n");
+}
+
+#[test]
+fn main_without_parameters_ignores_the_arguments() {
+    let path = source_file(
+        "main_without_arguments",
+        "main.ol",
+        r#"class Main { def main = println "hi"; }"#,
+    );
+    let output = ol([path.as_os_str(), "ignored".as_ref()]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "hi\n");
+}
+
+#[test]
+fn version_counts_the_builtin_methods() {
+    let methods = ol::method::default_methods();
+    let method_count = methods.values().map(HashMap::len).sum::<usize>()
+        + ol::method::object_methods().len()
+        + ol::method::universal_methods().len();
+    let output = ol(["--version"]);
+    assert!(output.status.success());
+    assert_eq!(
+        stdout(&output),
+        format!(
+            "ol {} ({} builtin types, {method_count} builtin methods)\n",
+            env!("CARGO_PKG_VERSION"),
+            methods.len()
+        )
+    );
+}
+
+#[test]
+fn check_does_not_run_the_program() {
+    let path = source_file(
+        "check_valid",
+        "main.ol",
+        r#"class Main { def main = println "ran"; }"#,
+    );
+    let output = ol(["--check".as_ref(), path.as_os_str()]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "");
+}
+
+#[test]
+fn check_reports_resolution_errors() {
+    let path = source_file(
+        "check_invalid",
+        "main.ol",
+        "class Main { def main = undefined; }",
+    );
+    let output = ol(["--check".as_ref(), path.as_os_str()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("variable `undefined` is not defined"));
+}
+
+#[test]
+fn dump_ast_shows_resolved_variables() {
+    let path = source_file(
+        "dump_ast",
+        "main.ol",
+        r#"class Main { def main = let x = 1 in println "ran"; }"#,
+    );
+    let output = ol(["--dump-ast".as_ref(), path.as_os_str()]);
+    assert!(output.status.success());
+    let ast = stdout(&output);
+    assert!(ast.contains("name: \"Main\""));
+    assert!(ast.contains("LetIn"));
+    assert!(!ast.contains("ran\n"));
+}
+
+#[test]
+fn emit_tokens_splits_a_sign_after_an_operand() {
+    let path = source_file("emit_tokens", "main.ol", "x-1 f -1");
+    let output = ol(["--emit-tokens".as_ref(), path.as_os_str()]);
+    assert!(output.status.success());
+    assert_eq!(
+        stdout(&output),
+        "0..1 Identifier x
+1..2 Punctuation -
+2..3 Number 1
+4..5 Identifier f
+6..8 Number -1
+"
+    );
+}
+
+#[test]
+fn imported_classes_can_be_used() {
+    source_file(
+        "imports",
+        "greeter.ol",
+        r#"class Greeter { def greet name = concat "hello " name; }"#,
+    );
+    let main = source_file(
+        "imports",
+        "main.ol",
+        r#"import "greeter.ol"
+        class Main { def main = println (greet (new Greeter) "world"); }"#,
+    );
+    let output = ol([&main]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "hello world\n");
+}
+
+#[test]
+fn import_cycles_are_rejected() {
+    source_file("import_cycle", "a.ol", r#"import "b.ol""#);
+    let b = source_file("import_cycle", "b.ol", r#"import "a.ol""#);
+    let output = ol([&b]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("import cycle"));
+}
+
+#[test]
+fn time_reports_each_phase_on_stderr() {
+    let path = source_file(
+        "time",
+        "main.ol",
+        r#"class Main { def main = println "ran"; }"#,
+    );
+    let output = ol(["--time".as_ref(), path.as_os_str()]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "ran\n");
+    let timings = stderr(&output);
+    for phase in ["parse: ", "resolve: ", "load: ", "run: "] {
+        assert!(timings.contains(phase), "{timings}");
+    }
+}
+
+#[test]
+fn short_version_flag_is_the_same_as_the_long_one() {
+    assert_eq!(ol(["-V"]).stdout, ol(["--version"]).stdout);
+}
+
+#[test]
+fn help_prints_the_usage() {
+    for flag in ["--help", "-h"] {
+        let output = ol([flag]);
+        assert!(output.status.success());
+        let help = stdout(&output);
+        assert!(help.starts_with("usage: ol [options] [<file>"), "{help}");
+        assert!(help.contains("--version"), "{help}");
+    }
+}
+
+#[test]
+fn unknown_flags_are_errors() {
+    let output = ol(["--frobnicate"]);
+    assert!(!output.status.success());
+    let stderr = stderr(&output);
+    assert!(stderr.contains("unknown flag `--frobnicate`"), "{stderr}");
+    assert!(stderr.contains("usage: ol"), "{stderr}");
+}
@@ -0,0 +1,95 @@
+//! Helpers shared by the integration tests.
+
+// Each test file only uses some of these.
+#![allow(dead_code)]
+
+use ol::{
+    main_class, parse, resolve,
+    value::Value,
+    vm::{SharedBuffer, VM},
+};
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+fn main_returning(expression: &str) -> String {
+    format!("class Main {{ def main = {expression}; }}")
+}
+
+/// Runs a program whose `main` method is `expression`, returning its value.
+pub fn eval(expression: &str) -> Value {
+    run(&main_returning(expression))
+}
+
+/// `eval` followed by `to_string`, which is the easiest way to check lists.
+pub fn show(expression: &str) -> String {
+    eval(expression).to_string()
+}
+
+/// Like `eval`, for expressions that are expected to fail. Returns the error
+/// along with its causes.
+pub fn eval_err(expression: &str) -> String {
+    run_err(&main_returning(expression))
+}
+
+pub fn run(source: &str) -> Value {
+    ol::run_source(source).unwrap_or_else(|err| panic!("{err:?}"))
+}
+
+pub fn run_err(source: &str) -> String {
+    match ol::run_source(source) {
+        Ok(value) => panic!("expected an error, got `{value}`"),
+        Err(err) => format!("{err:#}"),
+    }
+}
+
+/// Runs `source`, returning what `main` returned along with everything the
+/// program printed to standard output.
+pub fn run_printing(source: &str) -> (Value, String) {
+    let buffer = SharedBuffer::default();
+    let mut vm = VM::with_output(buffer.clone());
+    let program = parse::program(source).expect("program should parse");
+    let program = resolve::program(program, &mut resolve::Warnings::default())
+        .expect("program should resolve");
+    let class_ids = vm.load_program(program).expect("program should load");
+    let main_class = main_class(&class_ids).expect("program has a `Main`");
+    let value = vm.run(main_class).unwrap_or_else(|err| panic!("{err:?}"));
+    let printed = String::from_utf8(buffer.take()).expect("output is UTF-8");
+    (value, printed)
+}
+
+/// A path in a directory of its own for each test, so that tests running in
+/// parallel don't step on each other's files.
+pub fn temp_path(test: &str, file_name: &str) -> PathBuf {
+    let directory = Path::new(env!("CARGO_TARGET_TMPDIR")).join(test);
+    std::fs::create_dir_all(&directory).expect("temp dir can be created");
+    directory.join(file_name)
+}
+
+/// Writes a source file for the binary to run.
+pub fn source_file(test: &str, file_name: &str, source: &str) -> PathBuf {
+    let path = temp_path(test, file_name);
+    std::fs::write(&path, source).expect("source file can be written");
+    path
+}
+
+/// Runs the `ol` binary.
+pub fn ol<I, S>(arguments: I) -> Output
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+{
+    Command::new(env!("CARGO_BIN_EXE_ol"))
+        .args(arguments)
+        .output()
+        .expect("`ol` can be started")
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).expect("stdout is UTF-8")
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).expect("stderr is UTF-8")
+}
@@ -0,0 +1,573 @@
+//! Tests of the language itself, run through `ol::run_source`.
+
+mod common;
+
+use common::{eval, eval_err, run, run_err, run_printing, show};
+use ol::{value::Value, vm::VM};
+
+#[test]
+fn var_can_be_reassigned() {
+    let counter = "var i = 0, sum = 0 in do {
+        while (i < 5) { sum = sum + i; i = i + 1 };
+        sum
+    }";
+    assert_eq!(eval(counter), Value::I32(10));
+}
+
+#[test]
+fn let_cannot_be_reassigned() {
+    assert!(eval_err("let x = 1 in x = 2")
+        .contains("cannot assign to immutable `x`"));
+}
+
+#[test]
+fn duplicate_class_names_are_rejected() {
+    let source = "class Foo {} class Foo {} class Main { def main = (); }";
+    assert!(run_err(source).contains("class `Foo` is defined more than once"));
+}
+
+#[test]
+fn duplicate_parameters_are_rejected() {
+    let source = "class Main { def main = (); def f x x = x; }";
+    assert!(run_err(source)
+        .contains("method `f` has more than one parameter named `x`"));
+}
+
+#[test]
+fn parameters_named_this_are_rejected() {
+    let source = "class Main { def main = (); def f this = this; }";
+    assert!(run_err(source)
+        .contains("method `f` cannot have a parameter named `this`"));
+}
+
+#[test]
+fn if_without_else() {
+    assert_eq!(eval("if (true) { 1 }"), Value::I32(1));
+    assert_eq!(eval("if (false) { 1 }"), Value::Unit);
+}
+
+#[test]
+fn sign_before_a_digit_is_part_of_the_literal() {
+    assert_eq!(eval("let x = 3 in add x -1"), Value::I32(2));
+    assert_eq!(eval("let x = 3 in max x -10"), Value::I32(3));
+}
+
+#[test]
+fn sign_after_whitespace_or_an_operand_is_an_operator() {
+    assert_eq!(eval("let x = 3 in x - 1"), Value::I32(2));
+    assert_eq!(eval("let x = 3 in x-1"), Value::I32(2));
+    assert_eq!(eval("let x = 3 in x+1"), Value::I32(4));
+    assert_eq!(eval("let x = 3 in x - -1"), Value::I32(4));
+}
+
+#[test]
+fn multiplication_binds_tighter_than_addition() {
+    assert_eq!(eval("1 + 2 * 3"), Value::I32(7));
+    assert_eq!(eval("(1 + 2) * 3"), Value::I32(9));
+    assert_eq!(eval("7 % 4 + 10 / 5"), Value::I32(5));
+}
+
+#[test]
+fn arithmetic_is_left_associative() {
+    assert_eq!(eval("10 - 3 - 2"), Value::I32(5));
+    assert_eq!(eval("64 / 4 / 2"), Value::I32(8));
+}
+
+#[test]
+fn comparisons_bind_looser_than_arithmetic() {
+    assert_eq!(eval("1 + 2 == 3"), Value::Bool(true));
+    assert_eq!(eval("2 * 3 != 6"), Value::Bool(false));
+    assert_eq!(eval("1 < 2"), Value::Bool(true));
+    assert_eq!(eval("2 <= 1 + 1"), Value::Bool(true));
+    assert_eq!(eval("1 > 2"), Value::Bool(false));
+    assert_eq!(eval("3 >= 4 - 1"), Value::Bool(true));
+}
+
+#[test]
+fn operators_apply_to_method_calls() {
+    assert_eq!(eval("max 1 5 + min 2 3"), Value::I32(7));
+}
+
+#[test]
+fn unary_operators() {
+    assert_eq!(eval("-5"), Value::I32(-5));
+    assert_eq!(eval("- 5"), Value::I32(-5));
+    assert_eq!(eval("!true"), Value::Bool(false));
+    assert_eq!(eval("!(1 == 2)"), Value::Bool(true));
+}
+
+#[test]
+fn unary_minus_binds_tighter_than_binary_operators() {
+    assert_eq!(eval("let a = 1, b = 5 in -a + b"), Value::I32(4));
+    assert_eq!(eval("let a = 2 in -a * 3"), Value::I32(-6));
+}
+
+#[test]
+fn empty_method_body_is_unit() {
+    let source = "class Main { def main = nothing this; def nothing = ; }";
+    assert_eq!(run(source), Value::Unit);
+}
+
+#[test]
+fn list_comprehension() {
+    assert_eq!(show("[x * 2 for x in range 0 4]"), "[0, 2, 4, 6]");
+    assert_eq!(
+        show("[x for x in range 0 10 if x % 3 == 0]"),
+        "[0, 3, 6, 9]"
+    );
+    assert_eq!(show("[x for x in range 0 0]"), "[]");
+}
+
+#[test]
+fn list_comprehension_variable_is_scoped_to_it() {
+    assert_eq!(
+        eval("let x = 10 in let xs = [x + 1 for x in range 0 2] in x"),
+        Value::I32(10)
+    );
+    assert_eq!(
+        show("let y = 10 in [x + y for x in range 0 2 if x != y]"),
+        "[10, 11]"
+    );
+    assert_eq!(
+        show("[[x * 10 + y for y in range 0 2] for x in range 1 3]"),
+        "[[10, 11], [20, 21]]"
+    );
+}
+
+#[test]
+fn recursive_methods_compute_correct_results() {
+    let source = "class Main {
+        def main = fib this 20;
+        def fib n = if (n < 2) { n } else { fib this (n - 1) + fib this (n - 2) };
+    }";
+    assert_eq!(run(source), Value::I32(6765));
+}
+
+#[test]
+fn method_lookups_stay_correct_across_types() {
+    let source = r#"class Main {
+        def main = do { to_string (add 1 2); to_string (add 1.5 2.0) };
+    }"#;
+    assert_eq!(run(source), Value::String("3.5".into()));
+}
+
+#[test]
+fn tail_recursion_does_not_overflow_the_stack() {
+    let source = "class Main {
+        def main = sum this 100000 0;
+        def sum n total = if (n == 0) { total } else { sum this (n - 1) (total + n % 7) };
+    }";
+    assert_eq!(run(source), Value::I32(300_000));
+}
+
+#[test]
+fn runtime_errors_point_at_the_line() {
+    let source = "class Main {
+        def main =
+            foo 1;
+    }";
+    let err = run_err(source);
+    assert!(err.contains("at line 3"), "{err}");
+    assert!(err.contains("no method named `foo`"), "{err}");
+}
+
+#[test]
+fn methods_of_a_type_take_precedence_over_universal_ones() {
+    let source = r#"class Main {
+        def main = concat (to_string (new Point)) " " (to_string 1);
+    }
+    class Point { def to_string = "a point"; }"#;
+    assert_eq!(run(source), Value::String("a point 1".into()));
+}
+
+#[test]
+fn return_stops_the_rest_of_a_do_block() {
+    let source = r#"class Main {
+        def main = do { println "before"; return 1; println "after"; 2 };
+    }"#;
+    let (value, printed) = run_printing(source);
+    assert_eq!(value, Value::I32(1));
+    assert_eq!(printed, "before\n");
+}
+
+#[test]
+fn return_exits_the_whole_method_from_a_loop() {
+    let source = "class Main {
+        def main = first_multiple_of this 7;
+        def first_multiple_of n = var i = 1 in do {
+            while (true) { if (i % n == 0) { return i }; i = i + 1 };
+            -1
+        };
+    }";
+    assert_eq!(run(source), Value::I32(7));
+}
+
+#[test]
+fn return_only_exits_the_innermost_method() {
+    let source = "class Main {
+        def main = inner this + 1;
+        def inner = do { return 10; 20 };
+    }";
+    assert_eq!(run(source), Value::I32(11));
+}
+
+#[test]
+fn break_ends_the_loop_early() {
+    let counting = "var i = 0 in do {
+        while (true) { if (i == 5) { break }; i = i + 1 };
+        i
+    }";
+    assert_eq!(eval(counting), Value::I32(5));
+}
+
+#[test]
+fn continue_skips_to_the_next_iteration() {
+    let odd_sum = "var i = 0, sum = 0 in do {
+        while (i < 10) {
+            i = i + 1;
+            if (i % 2 == 0) { continue };
+            sum = sum + i
+        };
+        sum
+    }";
+    assert_eq!(eval(odd_sum), Value::I32(25));
+}
+
+#[test]
+fn break_outside_of_a_loop_is_an_error() {
+    assert!(run_err("class Main { def main = break; }")
+        .contains("`break` outside of a loop"));
+    assert!(run_err("class Main { def main = continue; }")
+        .contains("`continue` outside of a loop"));
+}
+
+#[test]
+fn blocks_evaluate_to_their_last_step() {
+    assert_eq!(eval("do {}"), Value::Unit);
+    assert_eq!(eval("do { 1 }"), Value::I32(1));
+    assert_eq!(eval("do { 1; 2 }"), Value::I32(2));
+    assert_eq!(eval("do{1 ;2}"), Value::I32(2));
+    assert_eq!(eval("if (true) {}"), Value::Unit);
+}
+
+#[test]
+fn trailing_semicolon_discards_the_last_value() {
+    assert_eq!(eval("do { 1; }"), Value::Unit);
+    assert_eq!(eval("do { 1; 2; }"), Value::Unit);
+    assert_eq!(eval("var x = 0 in do { do { x = 1; }; x }"), Value::I32(1));
+}
+
+#[test]
+fn else_if_chain() {
+    let source = r#"class Main {
+        def main = concat (describe this -1) (describe this 0) (describe this 1);
+        def describe n = if (n < 0) { "-" } else if (n == 0) { "0" } else { "+" };
+    }"#;
+    assert_eq!(run(source), Value::String("-0+".into()));
+}
+
+#[test]
+fn methods_are_dispatched_by_arity() {
+    let source = "class Main {
+        def main = f this 1 + f this 1 2;
+        def f x = x;
+        def f x y = x * 100 + y;
+    }";
+    assert_eq!(run(source), Value::I32(103));
+}
+
+#[test]
+fn calls_without_a_matching_arity_are_errors() {
+    let source = "class Main {
+        def main = f this 1 2 3;
+        def f x = x;
+        def f x y = x + y;
+    }";
+    assert!(run_err(source).contains(
+        "method `f` of type `Main` expected 1 or 2 arguments, got 3"
+    ));
+}
+
+#[test]
+fn calls_with_too_few_or_too_many_arguments_are_errors() {
+    let source = "class Main { def main = f this 1; def f x y = x + y; }";
+    assert!(run_err(source)
+        .contains("method `f` of type `Main` expected 2 arguments, got 1"));
+    let source = "class Main { def main = f this 1 2 3; def f x y = x + y; }";
+    assert!(run_err(source)
+        .contains("method `f` of type `Main` expected 2 arguments, got 3"));
+    let source = "class Main { def main = f this 1 2; def f x = x; }";
+    assert!(run_err(source)
+        .contains("method `f` of type `Main` expected 1 argument, got 2"));
+}
+
+#[test]
+fn let_with_several_bindings() {
+    assert_eq!(eval("let x = 1, y = add x 1 in y"), Value::I32(2));
+    assert_eq!(
+        eval("let x = 1, y = x + 1, z = x + y in z * 10"),
+        Value::I32(30)
+    );
+    assert_eq!(eval("let x = 1, x = x + 1 in x"), Value::I32(2));
+}
+
+#[test]
+fn do_blocks() {
+    assert_eq!(eval("do { }"), Value::Unit);
+    assert_eq!(eval("do { 1; 2 }"), Value::I32(2));
+}
+
+#[test]
+fn blocks_need_do_outside_of_if_and_while() {
+    assert!(
+        run_err("class Main { def main = { 1; 2 }; }").contains("syntax error")
+    );
+}
+
+#[test]
+fn match_picks_the_matching_arm() {
+    assert_eq!(eval("match 2 { 1 => 10; 2 => 20; _ => 0 }"), Value::I32(20));
+    assert_eq!(eval(r#"match "b" { "a" => 1; "b" => 2 }"#), Value::I32(2));
+}
+
+#[test]
+fn match_falls_back_to_the_default_arm() {
+    assert_eq!(eval("match 5 { 1 => 10; _ => 0 }"), Value::I32(0));
+    assert_eq!(eval("match 5 { _ => 0 }"), Value::I32(0));
+}
+
+#[test]
+fn match_without_a_matching_arm_is_an_error() {
+    assert!(eval_err("match 5 { 1 => 10; 2 => 20 }")
+        .contains("no arm of `match` matches 5"));
+}
+
+#[test]
+fn methods_are_inherited() {
+    let source = r#"class Main {
+        def main = concat (speak (new Dog)) " " (name (new Dog));
+    }
+    class Animal { def speak = "..."; def name = "animal"; }
+    class Dog extends Animal { def speak = "woof"; }"#;
+    assert_eq!(run(source), Value::String("woof animal".into()));
+}
+
+#[test]
+fn fields_are_inherited() {
+    let source =
+        "class Main { def main = get (new B) \"a\" + get (new B) \"b\"; }
+    class A { field a = 1; }
+    class B extends A { field b = 2; }";
+    assert_eq!(run(source), Value::I32(3));
+}
+
+const OPTIONAL_CHAINING: &str = "class Outer { field inner = new Inner; }
+    class Inner { field value = 42; field nothing = (); }";
+
+fn optional_chain(chain: &str) -> Value {
+    run(&format!(
+        "class Main {{ def main = {chain}; }} {OPTIONAL_CHAINING}"
+    ))
+}
+
+#[test]
+fn optional_chaining_reads_fields() {
+    assert_eq!(optional_chain("(new Outer)?.inner?.value"), Value::I32(42));
+}
+
+#[test]
+fn optional_chaining_stops_at_unit() {
+    let chain = "(new Outer)?.inner?.nothing?.value";
+    assert_eq!(optional_chain(chain), Value::Unit);
+}
+
+#[test]
+fn optional_chaining_treats_missing_fields_as_unit() {
+    let chain = "(new Outer)?.missing?.value";
+    assert_eq!(optional_chain(chain), Value::Unit);
+}
+
+#[test]
+fn optional_chaining_binds_tighter_than_method_calls() {
+    let chain = "to_string (new Outer)?.inner?.value";
+    assert_eq!(optional_chain(chain), Value::String("42".into()));
+}
+
+#[test]
+fn optional_chaining_on_other_values_is_an_error() {
+    assert!(eval_err("5?.value").contains("expected an object, got `I32`"));
+}
+
+#[test]
+fn undefined_superclasses_are_rejected() {
+    let source = "class Main { def main = (); } class A extends Nope {}";
+    assert!(
+        run_err(source).contains("class `A` extends undefined class `Nope`")
+    );
+}
+
+#[test]
+fn inheritance_cycles_are_rejected() {
+    let source = "class Main { def main = (); }
+    class A extends B {}
+    class B extends A {}";
+    let err = run_err(source);
+    assert!(
+        err.contains("inheritance cycle: `A` -> `B` -> `A`"),
+        "{err}"
+    );
+    assert!(!err.contains("`B` -> `A` -> `B`"), "{err}");
+}
+
+#[test]
+fn closures_can_be_applied() {
+    assert_eq!(eval("apply (fn x -> x * 2) 21"), Value::I32(42));
+    assert_eq!(eval("apply (fn x y -> x - y) 5 3"), Value::I32(2));
+    assert_eq!(eval("apply (fn -> 7)"), Value::I32(7));
+}
+
+#[test]
+fn closures_capture_local_variables() {
+    assert_eq!(
+        eval("let offset = 10 in let add_offset = fn x -> x + offset in apply add_offset 5"),
+        Value::I32(15)
+    );
+    assert_eq!(
+        eval("let make_adder = fn n -> fn x -> x + n in apply (apply make_adder 3) 4"),
+        Value::I32(7)
+    );
+}
+
+#[test]
+fn closures_can_return_early() {
+    assert_eq!(eval("apply (fn x -> do { return x; 0 }) 3"), Value::I32(3));
+}
+
+#[test]
+fn let_rec_closures_can_call_themselves() {
+    let factorial = "let rec factorial = fn n ->
+        if (n == 0) { 1 } else { n * (apply factorial (n - 1)) }
+    in apply factorial 5";
+    assert_eq!(eval(factorial), Value::I32(120));
+}
+
+#[test]
+fn let_rec_closures_capture_other_local_variables() {
+    let sum_every_other = "let step = 2 in let rec sum = fn n ->
+        if (n < 1) { 0 } else { n + (apply sum (n - step)) }
+    in apply sum 7";
+    assert_eq!(eval(sum_every_other), Value::I32(16));
+}
+
+#[test]
+fn let_rec_can_only_bind_closures() {
+    assert!(eval_err("let rec x = 1 in x")
+        .contains("`let rec` can only bind a closure"));
+}
+
+#[test]
+fn plain_let_closures_cannot_call_themselves() {
+    assert!(eval_err("let f = fn n -> apply f n in apply f 1")
+        .contains("variable `f` is not defined"));
+}
+
+#[test]
+fn times_calls_the_closure_with_each_index() {
+    let source =
+        "class Main { def main = times 3 (fn i -> println (to_string i)); }";
+    assert_eq!(run_printing(source), (Value::Unit, "0\n1\n2\n".to_owned()));
+}
+
+#[test]
+fn empty_programs_are_reported_as_such() {
+    assert!(run_err("").contains("program is empty"));
+    assert!(run_err("  // nothing here\n").contains("program is empty"));
+}
+
+#[test]
+fn programs_without_main_are_reported_as_such() {
+    assert!(run_err("class NotMain {}").contains("program has no `Main` class"));
+}
+
+#[test]
+fn main_with_more_than_one_parameter_is_an_error() {
+    let source = "class Main { def main a b = (); }";
+    assert!(run_err(source).contains("`main` must take at most one parameter"));
+}
+
+#[test]
+fn run_source_returns_what_main_returns() {
+    let source =
+        "class Main { def main = square this 7; def square n = n * n; }";
+    assert_eq!(
+        ol::run_source(source).expect("program runs"),
+        Value::I32(49)
+    );
+}
+
+#[test]
+fn run_source_reports_syntax_errors() {
+    let err = ol::run_source("class Main {").expect_err("class is unclosed");
+    assert_eq!(err.to_string(), "syntax error");
+}
+
+#[test]
+fn eval_expression_evaluates_in_the_given_vm() {
+    let mut vm = VM::sandboxed();
+    let value = ol::eval_expression(&mut vm, "let x = 20 in x + 1 + 1");
+    assert_eq!(value.expect("expression evaluates"), Value::I32(22));
+    assert!(ol::eval_expression(&mut vm, "undefined").is_err());
+}
+
+#[test]
+fn fields_are_initialized_by_new() {
+    let source = r#"class Main {
+        def main = let counter = new Counter in concat (get counter "label") (get counter "count");
+    }
+    class Counter { field count = 1 + 2; field label = "count: "; }"#;
+    assert_eq!(run(source), Value::String("count: 3".into()));
+}
+
+#[test]
+fn return_in_a_field_initializer_is_an_error() {
+    let source = "class Main { field x = return 1; def main = (); }";
+    assert!(run_err(source).contains("`return` outside of a method"));
+}
+
+#[test]
+fn underscores_can_group_digits() {
+    assert_eq!(eval("1_000_000"), Value::I32(1_000_000));
+    assert_eq!(eval("-1_0"), Value::I32(-10));
+}
+
+#[test]
+fn misplaced_underscores_in_numbers_are_syntax_errors() {
+    for number in ["1_", "1__0", "_1 + 1", "1_.5"] {
+        let source = format!("class Main {{ def main = {number}; }}");
+        let err = run_err(&source);
+        assert!(
+            err.contains("syntax error") || err.contains("`_1` is not defined"),
+            "{number}: {err}"
+        );
+    }
+}
+
+#[test]
+fn a_lone_sign_is_not_a_number() {
+    assert!(run_err("class Main { def main = -; }").contains("syntax error"));
+}
+
+#[test]
+fn errors_name_classes_rather_than_class_ids() {
+    let source =
+        "class Main { def main = missing (new Point); } class Point {}";
+    let err = run_err(source);
+    assert!(
+        err.contains("type `Point` has no method named `missing`"),
+        "{err}"
+    );
+    let source =
+        "class Main { def main = assert_eq (new Point) 1; } class Point {}";
+    let err = run_err(source);
+    assert!(err.contains("cannot compare `Point` with `I32`"), "{err}");
+}